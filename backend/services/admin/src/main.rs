@@ -0,0 +1,397 @@
+//! `aurora-admin`, for operational tasks that would otherwise mean poking at Postgres by hand.
+//!
+//! Configured through the same environment (and `.env`) as `aurora-api`: `DATABASE_URL`,
+//! and `HMAC_SECURITY_KEY` plus `TOKEN_EXPIRATION_TIME` for the token commands.
+//!
+//! Passwords are read from stdin, so they don't end up in the shell history or process list.
+//! e.g. `aurora-admin reset-password nova < password.txt`.
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, IsTerminal, Write},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context};
+use aurora_api::{
+    models::{
+        self,
+        user::{self, User},
+    },
+    v1::{
+        routes::auth::RegisterRequest,
+        token::{AuthenticationToken, FIRST_EPOCH, TOKEN_EXPIRATION_TIME},
+        validation::{self, field_errors},
+    },
+};
+use clap::{Parser, Subcommand};
+use sqlx::{migrate::Migrate, PgPool};
+use time::format_description::well_known::Rfc3339;
+use validator::Validate;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Operational tasks for an Aurora deployment.")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create a user, with the password read from stdin.
+    CreateUser {
+        username: String,
+        email: String,
+
+        /// Mark the email as verified, rather than leaving that to the user.
+        #[arg(long)]
+        verified: bool,
+    },
+
+    /// Set a new password for a user, read from stdin. Revokes their sessions and OAuth2 authorizations, and unlocks logins.
+    ResetPassword { user: UserRef },
+
+    /// Print a new token for a user.
+    MintToken { user: UserRef },
+
+    /// Show what a token carries, and verify it. Fails if it isn't valid.
+    DecodeToken { token: String },
+
+    /// Revoke every token issued to a user until now.
+    RevokeSessions { user: UserRef },
+
+    /// Apply pending database migrations.
+    Migrate {
+        /// Only list the pending migrations.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// A user, by ID or by username. Anything numeric is taken as ID.
+#[derive(Debug, Clone, PartialEq)]
+enum UserRef {
+    Id(i64),
+    Username(String),
+}
+
+impl FromStr for UserRef {
+    type Err = std::convert::Infallible;
+
+    fn from_str(user: &str) -> Result<Self, Self::Err> {
+        Ok(match user.parse() {
+            Ok(id) => Self::Id(id),
+            Err(_) => Self::Username(user.to_owned()),
+        })
+    }
+}
+
+impl std::fmt::Display for UserRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "with the ID {id}"),
+            Self::Username(username) => write!(f, "named {username}"),
+        }
+    }
+}
+
+impl UserRef {
+    async fn find(&self, pool: &PgPool) -> anyhow::Result<User> {
+        let user = match self {
+            Self::Id(id) => User::find_by_id(pool, *id).await?,
+            Self::Username(username) => User::find_by_username(pool, username).await?,
+        };
+
+        user.ok_or_else(|| anyhow!("There is no user {self}"))
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Unlike the servers, the CLI is run from anywhere, there may be no `.env` around.
+    dotenv::dotenv().ok();
+
+    match Cli::parse().command {
+        Command::CreateUser {
+            username,
+            email,
+            verified,
+        } => create_user(&connect().await?, username, email, verified).await,
+        Command::ResetPassword { user } => reset_password(&connect().await?, &user).await,
+        Command::MintToken { user } => {
+            require_env(&["HMAC_SECURITY_KEY", "TOKEN_EXPIRATION_TIME"])?;
+            mint_token(&connect().await?, &user).await
+        }
+        Command::DecodeToken { token } => {
+            require_env(&["HMAC_SECURITY_KEY", "TOKEN_EXPIRATION_TIME"])?;
+            decode_token(&token)
+        }
+        Command::RevokeSessions { user } => revoke_sessions(&connect().await?, &user).await,
+        Command::Migrate { dry_run } => migrate(&connect().await?, dry_run).await,
+    }
+}
+
+/// Connect to the database, without migrating it. That's left to [Command::Migrate].
+async fn connect() -> anyhow::Result<PgPool> {
+    require_env(&["DATABASE_URL"])?;
+    models::connect().await
+}
+
+/// Fail with a readable error if any of `vars` isn't set, instead of panicking once it's used.
+fn require_env(vars: &[&str]) -> anyhow::Result<()> {
+    for var in vars {
+        if std::env::var(var).is_err() {
+            bail!("{var} environment variable must be set");
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a password from the first line of stdin, prompting for it on terminals.
+///
+/// It is echoed on terminals, pipe it in to avoid that.
+fn read_password() -> anyhow::Result<String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        std::io::stderr().flush()?;
+    }
+
+    let mut password = String::new();
+    stdin.lock().read_line(&mut password)?;
+
+    let password = password.trim_end_matches(['\r', '\n']).to_owned();
+    if password.is_empty() {
+        bail!("No password given on stdin");
+    }
+
+    Ok(password)
+}
+
+/// Fail with the messages the API would answer with, if `request` is invalid.
+fn validate(request: &impl Validate) -> anyhow::Result<()> {
+    let Err(errors) = request.validate() else {
+        return Ok(());
+    };
+
+    let messages: Vec<String> = field_errors(&errors)
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors
+                .into_iter()
+                .map(move |err| format!("{field}: {}", err.message))
+        })
+        .collect();
+
+    bail!("{}", messages.join("\n"))
+}
+
+fn hash_password(password: &str) -> anyhow::Result<String> {
+    user::hash_password(password).map_err(|err| anyhow!("Failed to hash password: {err}"))
+}
+
+async fn create_user(
+    pool: &PgPool,
+    username: String,
+    email: String,
+    verified: bool,
+) -> anyhow::Result<()> {
+    // Held to the same rules as registrations.
+    let request = RegisterRequest {
+        username: username.trim().to_owned(),
+        email: email.trim().to_owned(),
+        password: read_password()?,
+    };
+    validate(&request)?;
+
+    let password_hash = hash_password(&request.password)?;
+    let user = match User::create(
+        pool,
+        &request.username,
+        Some(&request.email),
+        &password_hash,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(err) => match user::taken_field(&err) {
+            Some(field) => bail!("This {field} is taken"),
+            None => return Err(err.into()),
+        },
+    };
+
+    if verified {
+        user.verify_email(pool).await?;
+    }
+
+    println!("Created user {} ({})", user.username, user.id);
+    Ok(())
+}
+
+async fn reset_password(pool: &PgPool, user: &UserRef) -> anyhow::Result<()> {
+    let user = user.find(pool).await?;
+    if user.bot {
+        bail!("{} is a bot, bots have no password", user.username);
+    }
+
+    // Held to the same rules as password resets through the API.
+    let password = read_password()?;
+    validation::validate_password(&password)
+        .map_err(|err| anyhow!("password: {}", validation::message(&err)))?;
+
+    // The same as resets through the API.
+    user.reset_password(pool, &hash_password(&password)?)
+        .await?;
+
+    println!(
+        "Reset the password of {} ({}), their sessions and OAuth2 authorizations are revoked and logins unlocked",
+        user.username, user.id
+    );
+    Ok(())
+}
+
+async fn mint_token(pool: &PgPool, user: &UserRef) -> anyhow::Result<()> {
+    let user = user.find(pool).await?;
+    let token: String = AuthenticationToken::new(user.id as u64)?.into();
+
+    // Only the token goes to stdout, so it can be piped.
+    if !user.bot {
+        eprintln!(
+            "Token of {} ({}), expires in {}s:",
+            user.username, user.id, *TOKEN_EXPIRATION_TIME
+        );
+    }
+    println!("{token}");
+    Ok(())
+}
+
+fn decode_token(token: &str) -> anyhow::Result<()> {
+    let token = AuthenticationToken::decode(token.trim())?;
+
+    // Nothing keeps a crafted token from carrying a time no date can represent.
+    let generated_at = FIRST_EPOCH.checked_add(time::Duration::milliseconds(token.generation_time));
+    let expires_at = generated_at.and_then(|generated_at| {
+        generated_at.checked_add(time::Duration::seconds(*TOKEN_EXPIRATION_TIME))
+    });
+
+    println!("User ID:      {}", token.user_id);
+    println!(
+        "Generated at: {} ({}ms after FIRST_EPOCH, {})",
+        format_time(generated_at)?,
+        token.generation_time,
+        FIRST_EPOCH.format(&Rfc3339)?
+    );
+    println!(
+        "Expires at:   {}{} (bot tokens don't expire)",
+        format_time(expires_at)?,
+        if token.expired() { ", expired" } else { "" }
+    );
+    if let Some(grant) = &token.grant {
+        println!("Client ID:    {}", grant.client_id);
+        println!("Scopes:       {}", grant.scopes);
+    }
+
+    token.verify().context("The token is not valid")?;
+    println!("Signature:    valid");
+
+    Ok(())
+}
+
+fn format_time(time: Option<time::OffsetDateTime>) -> anyhow::Result<String> {
+    match time {
+        Some(time) => Ok(time.format(&Rfc3339)?),
+        None => Ok("out of range".into()),
+    }
+}
+
+async fn revoke_sessions(pool: &PgPool, user: &UserRef) -> anyhow::Result<()> {
+    let user = user.find(pool).await?;
+    user.revoke_sessions(pool).await?;
+
+    println!("Revoked the sessions of {} ({})", user.username, user.id);
+    Ok(())
+}
+
+async fn migrate(pool: &PgPool, dry_run: bool) -> anyhow::Result<()> {
+    let applied: HashSet<i64> = {
+        let mut connection = pool.acquire().await?;
+
+        // A dry run never writes, not even the migrations table. Without it, nothing is applied.
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(&mut *connection)
+            .await?;
+        if !dry_run {
+            connection.ensure_migrations_table().await?;
+        }
+
+        match exists || !dry_run {
+            true => connection
+                .list_applied_migrations()
+                .await?
+                .into_iter()
+                .map(|migration| migration.version)
+                .collect(),
+            false => HashSet::new(),
+        }
+    };
+
+    let pending: Vec<_> = models::MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect();
+    if pending.is_empty() {
+        println!("The database is up to date");
+        return Ok(());
+    }
+
+    for migration in &pending {
+        println!("Pending: {} {}", migration.version, migration.description);
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    models::migrate(pool).await?;
+    println!("Applied {} migration(s)", pending.len());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["aurora-admin", "create-user", "nova", "nova@example.com"]);
+        assert!(matches!(
+            cli.command,
+            Command::CreateUser {
+                verified: false,
+                ..
+            }
+        ));
+
+        let cli = Cli::parse_from(["aurora-admin", "migrate", "--dry-run"]);
+        assert!(matches!(cli.command, Command::Migrate { dry_run: true }));
+    }
+
+    #[test]
+    fn test_user_ref() {
+        assert_eq!("42".parse(), Ok(UserRef::Id(42)));
+        assert_eq!("nova".parse(), Ok(UserRef::Username("nova".into())));
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(
+            format_time(Some(FIRST_EPOCH)).unwrap(),
+            "2024-01-23T00:00:00Z"
+        );
+        assert_eq!(format_time(None).unwrap(), "out of range");
+    }
+}
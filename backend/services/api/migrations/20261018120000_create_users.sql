@@ -0,0 +1,9 @@
+CREATE TABLE users (
+    id BIGINT PRIMARY KEY,
+    username TEXT NOT NULL,
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Usernames are unique regardless of their casing.
+CREATE UNIQUE INDEX users_username_key ON users (lower(username));
@@ -0,0 +1,13 @@
+-- Accounts created through signup get their ID from here.
+CREATE SEQUENCE users_id_seq OWNED BY users.id;
+SELECT setval('users_id_seq', COALESCE((SELECT max(id) FROM users), 0) + 1, false);
+ALTER TABLE users ALTER COLUMN id SET DEFAULT nextval('users_id_seq');
+
+ALTER TABLE users
+    ADD COLUMN email TEXT,
+    ADD COLUMN email_verified_at TIMESTAMPTZ,
+    -- Tokens generated before this are rejected, e.g. after a password reset.
+    ADD COLUMN sessions_valid_after TIMESTAMPTZ;
+
+-- Emails are unique regardless of their casing.
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
@@ -0,0 +1,5 @@
+-- Bots are users owned by the user who created them, everyone else has no owner.
+-- Bots have no password, their tokens are regenerated instead.
+ALTER TABLE users ADD COLUMN owner_id BIGINT REFERENCES users (id) ON DELETE CASCADE;
+
+CREATE INDEX users_owner_id_idx ON users (owner_id) WHERE owner_id IS NOT NULL;
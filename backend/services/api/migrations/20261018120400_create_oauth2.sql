@@ -0,0 +1,31 @@
+-- Applications registered as OAuth2 clients, see `src/v1/oauth2.rs`.
+CREATE TABLE oauth2_clients (
+    application_id BIGINT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    redirect_uris TEXT[] NOT NULL,
+    -- SHA-256 of the client secret, NULL for public clients (which rely on PKCE alone).
+    secret_hash TEXT
+);
+
+-- Issued authorization codes, deleted once exchanged. Only their hash is stored.
+CREATE TABLE oauth2_codes (
+    code_hash TEXT PRIMARY KEY,
+    application_id BIGINT NOT NULL REFERENCES oauth2_clients (application_id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    -- As sent to the authorization endpoint, the token request has to repeat it.
+    redirect_uri TEXT,
+    code_challenge TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- Applications a user authorized. Access tokens are only accepted while it exists,
+-- and if they were issued after it was created.
+CREATE TABLE oauth2_authorizations (
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    application_id BIGINT NOT NULL REFERENCES oauth2_clients (application_id) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    refresh_token_hash TEXT NOT NULL UNIQUE,
+    -- Set by the API, by the same clock the access tokens compared against it are generated with.
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (user_id, application_id)
+);
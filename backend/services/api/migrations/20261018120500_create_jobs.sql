@@ -0,0 +1,23 @@
+-- Background jobs, see `src/jobs.rs`. Deleted once they succeeded.
+CREATE TABLE jobs (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    last_error TEXT,
+    -- Set while a worker runs the job. A job whose lease ran out is picked up again.
+    locked_until TIMESTAMPTZ,
+    -- Set once every attempt failed, the job is kept around for inspection.
+    failed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX jobs_due_idx ON jobs (run_at) WHERE failed_at IS NULL;
+
+-- Recurring jobs, enqueued by whichever worker first sees them due.
+CREATE TABLE job_schedules (
+    name TEXT PRIMARY KEY,
+    next_run_at TIMESTAMPTZ NOT NULL
+);
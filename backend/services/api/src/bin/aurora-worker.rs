@@ -0,0 +1,46 @@
+//! Runs background jobs, for deployments running `aurora-api` with `WORKER=off`.
+//!
+//! Configured like `aurora-api`, see [aurora_api::jobs]. Any amount of workers can run at once.
+
+use aurora_api::{
+    jobs::{self, JobContext},
+    mail, models, require_env, shutdown_signal, telemetry,
+};
+use dotenv::dotenv;
+use tokio::sync::watch;
+
+#[macro_use]
+extern crate tracing;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv()?;
+
+    let telemetry = telemetry::init()?;
+
+    // Jobs mint tokens, see [mail::ActionMail].
+    require_env()?;
+
+    let pool = models::connect().await?;
+    models::migrate(&pool).await?;
+    let context = JobContext {
+        pool: pool.clone(),
+        mailer: mail::from_env()?,
+    };
+
+    let (stop_worker, worker_shutdown) = watch::channel(false);
+    let worker =
+        tokio::spawn(jobs::worker(context).run(jobs::concurrency_from_env(), worker_shutdown));
+
+    shutdown_signal().await;
+    info!("Shutting down, waiting for running jobs...");
+    stop_worker.send_replace(true);
+    worker.await?;
+
+    info!("Closing database connections...");
+    pool.close().await;
+
+    telemetry.shutdown().await;
+
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use sqlx::PgPool;
+use time::format_description::well_known::Rfc3339;
+use tokio::time::Duration;
+
+use crate::models::MIGRATOR;
+
+/// How long the database gets to answer a readiness probe.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn register_routes() -> Router<PgPool> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/version", get(version))
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Health {
+    pub status: &'static str,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub database: bool,
+    pub migrations: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Version {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub build_time: String,
+}
+
+/// GET /healthz - liveness probe, answers as long as the process is alive.
+pub async fn healthz() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+/// GET /readyz - readiness probe, answers 503 unless the database is reachable
+///               and every migration has been applied.
+pub async fn readyz(State(pool): State<PgPool>) -> (StatusCode, Json<Readiness>) {
+    let applied = tokio::time::timeout(READINESS_TIMEOUT, async {
+        sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(&pool)
+            .await
+    })
+    .await;
+
+    let (database, migrations) = match applied {
+        Ok(Ok(applied)) => (
+            true,
+            MIGRATOR
+                .iter()
+                .filter(|migration| !migration.migration_type.is_down_migration())
+                .all(|migration| applied.contains(&migration.version)),
+        ),
+        Ok(Err(err)) => {
+            warn!("Readiness check failed: {err}");
+            (
+                // The database answered, it just doesn't know about migrations yet.
+                matches!(err, sqlx::Error::Database(_)),
+                false,
+            )
+        }
+        Err(_) => {
+            warn!("Readiness check timed out after {READINESS_TIMEOUT:?}");
+            (false, false)
+        }
+    };
+
+    let (status_code, status) = match database && migrations {
+        true => (StatusCode::OK, "ok"),
+        false => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+    };
+
+    (
+        status_code,
+        Json(Readiness {
+            status,
+            database,
+            migrations,
+        }),
+    )
+}
+
+/// GET /version - the version, git commit and build time of this build.
+pub async fn version() -> Json<Version> {
+    let build_time = env!("AURORA_BUILD_TIME")
+        .parse()
+        .ok()
+        .and_then(|timestamp| time::OffsetDateTime::from_unix_timestamp(timestamp).ok())
+        .and_then(|timestamp| timestamp.format(&Rfc3339).ok())
+        .unwrap_or_else(|| "unknown".into());
+
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("AURORA_GIT_COMMIT"),
+        build_time,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+    use tracing_test::traced_test;
+
+    /// Router backed by a database that is never reachable.
+    fn app() -> Router {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(500))
+            .connect_lazy("postgres://aurora@127.0.0.1:1/aurora")
+            .unwrap();
+
+        register_routes().with_state(pool)
+    }
+
+    async fn get_json(uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = app()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_healthz() {
+        let (status, body) = get_json("/healthz").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_readyz_without_database() {
+        let (status, body) = get_json("/readyz").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["database"], false);
+        assert_eq!(body["migrations"], false);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_version() {
+        let (status, body) = get_json("/version").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!body["commit"].as_str().unwrap().is_empty());
+        assert_ne!(body["build_time"], "unknown");
+    }
+}
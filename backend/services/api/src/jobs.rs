@@ -0,0 +1,362 @@
+//! Background jobs, queued in Postgres.
+//!
+//! Jobs are typed, see [Job]. Their payload is stored as JSON and handed back to [Job::run]
+//! by whichever worker claims it first (`SELECT ... FOR UPDATE SKIP LOCKED`), so any amount
+//! of workers can share the queue. Failed jobs are retried with exponential backoff, and kept
+//! along with their last error once every attempt failed. Recurring jobs are added through
+//! [Worker::schedule].
+//!
+//! A job may run more than once, e.g. when its worker died before it could record the result
+//! and the [LEASE] ran out, so jobs must be safe to repeat.
+//!
+//! The worker is configured with:
+//! - `WORKER`: `inline` (default) runs it inside `aurora-api`. `off` leaves the jobs to
+//!   separately deployed `aurora-worker` processes.
+//! - `WORKER_CONCURRENCY` (default 2): how many jobs a process runs at the same time.
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+
+use axum::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::{sync::watch, task::JoinSet, time::Duration};
+use tracing::Instrument;
+
+use crate::{
+    mail::{ActionMail, Mail, Mailer},
+    models::{job, login_throttle, oauth2},
+};
+
+/// How long an idle worker waits before looking for due jobs again.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a claimed job is reserved for its worker, before others may pick it up again.
+pub const LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// Delay before the first retry, doubled for every further one.
+const BASE_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Upper bound of the delay between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How often [Cleanup] runs.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long jobs which failed for good are kept around, for inspection.
+const FAILED_JOB_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// What jobs get to work with.
+#[derive(Clone)]
+pub struct JobContext {
+    pub pool: PgPool,
+    pub mailer: Arc<dyn Mailer>,
+}
+
+/// A kind of background job, its value being the payload.
+#[async_trait]
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Identifies the kind in the queue, it must never change once jobs were enqueued.
+    const KIND: &'static str;
+
+    /// Attempts before the job is given up.
+    const MAX_ATTEMPTS: i32 = 5;
+
+    async fn run(self, context: &JobContext) -> anyhow::Result<()>;
+}
+
+/// Queue `job` to run as soon as a worker is free.
+pub async fn enqueue<J: Job>(pool: &PgPool, job: &J) -> sqlx::Result<i64> {
+    enqueue_in(pool, job, Duration::ZERO).await
+}
+
+/// Queue `job` to run after `delay`.
+///
+/// Panics if `job` can't be serialized, which only happens for maps with non-string keys.
+pub async fn enqueue_in<J: Job>(pool: &PgPool, job: &J, delay: Duration) -> sqlx::Result<i64> {
+    let payload = serde_json::to_string(job).expect("Failed to serialize job");
+    job::enqueue(pool, J::KIND, &payload, J::MAX_ATTEMPTS, delay).await
+}
+
+/// The delay before retrying a job which failed its `attempts`th attempt.
+pub fn backoff(attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).max(0) as u32;
+
+    BASE_BACKOFF
+        .checked_mul(2u32.saturating_pow(exponent))
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+/// Whether `aurora-api` runs the worker itself, configured through `WORKER`.
+pub fn inline_from_env() -> bool {
+    match std::env::var("WORKER").as_deref() {
+        Ok("inline") | Err(_) => true,
+        Ok("off") => false,
+        Ok(worker) => panic!("WORKER must be either 'inline' or 'off', got '{worker}'"),
+    }
+}
+
+/// How many jobs a worker runs at the same time, configured through `WORKER_CONCURRENCY`.
+pub fn concurrency_from_env() -> usize {
+    std::env::var("WORKER_CONCURRENCY")
+        .map(|concurrency| {
+            concurrency
+                .parse()
+                .expect("WORKER_CONCURRENCY must be a valid integer")
+        })
+        .unwrap_or(2)
+}
+
+/// The worker running every job of the backend.
+pub fn worker(context: JobContext) -> Worker {
+    Worker::new(context)
+        .register::<Mail>()
+        .register::<ActionMail>()
+        .schedule(&Cleanup, CLEANUP_INTERVAL)
+}
+
+type Handler = fn(JobContext, String) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+fn handle<J: Job>(
+    context: JobContext,
+    payload: String,
+) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>> {
+    Box::pin(async move {
+        let job: J = serde_json::from_str(&payload)?;
+        job.run(&context).await
+    })
+}
+
+/// A recurring job, see [Worker::schedule].
+struct Schedule {
+    kind: &'static str,
+    payload: String,
+    max_attempts: i32,
+    interval: Duration,
+}
+
+/// Claims and runs jobs of the kinds registered with it.
+pub struct Worker {
+    context: JobContext,
+    handlers: HashMap<&'static str, Handler>,
+    schedules: Vec<Schedule>,
+}
+
+impl Worker {
+    pub fn new(context: JobContext) -> Self {
+        Self {
+            context,
+            handlers: HashMap::new(),
+            schedules: Vec::new(),
+        }
+    }
+
+    /// Run jobs of the kind `J`.
+    pub fn register<J: Job>(mut self) -> Self {
+        self.handlers.insert(J::KIND, handle::<J>);
+        self
+    }
+
+    /// Enqueue `job` every `interval`, and run it.
+    ///
+    /// The schedule is shared by every worker through the database, so the job is enqueued
+    /// once per interval no matter how many of them run. It is due right away the very first time.
+    pub fn schedule<J: Job>(mut self, job: &J, interval: Duration) -> Self {
+        self.schedules.push(Schedule {
+            kind: J::KIND,
+            payload: serde_json::to_string(job).expect("Failed to serialize scheduled job"),
+            max_attempts: J::MAX_ATTEMPTS,
+            interval,
+        });
+        self.register::<J>()
+    }
+
+    /// Run jobs on `concurrency` tasks until `shutdown` turns `true`.
+    ///
+    /// Jobs already running are finished first.
+    pub async fn run(self, concurrency: usize, shutdown: watch::Receiver<bool>) {
+        info!(concurrency, "Worker started");
+
+        let worker = Arc::new(self);
+        let mut tasks = JoinSet::new();
+        for _ in 0..concurrency.max(1) {
+            tasks.spawn(worker.clone().work(shutdown.clone()));
+        }
+        while tasks.join_next().await.is_some() {}
+
+        info!("Worker stopped");
+    }
+
+    async fn work(self: Arc<Self>, mut shutdown: watch::Receiver<bool>) {
+        while !*shutdown.borrow() {
+            match self.tick().await {
+                // There may be more due jobs, keep going.
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => error!("Failed to poll jobs: {err}"),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {},
+                // Also fails once the sender is gone, there's no way to stop us anymore then.
+                changed = shutdown.changed() => if changed.is_err() { break },
+            }
+        }
+    }
+
+    /// Enqueue due schedules, then run the next due job. Returns whether there was one.
+    async fn tick(&self) -> sqlx::Result<bool> {
+        let pool = &self.context.pool;
+
+        for schedule in &self.schedules {
+            let enqueued = job::enqueue_scheduled(
+                pool,
+                schedule.kind,
+                schedule.kind,
+                &schedule.payload,
+                schedule.max_attempts,
+                schedule.interval,
+            )
+            .await?;
+
+            if enqueued {
+                debug!(kind = schedule.kind, "Scheduled job enqueued");
+            }
+        }
+
+        let Some(claimed) = job::claim(pool, LEASE).await? else {
+            return Ok(false);
+        };
+
+        let span = info_span!(
+            "job",
+            id = claimed.id,
+            kind = claimed.kind,
+            attempt = claimed.attempts
+        );
+
+        async {
+            match self.dispatch(&claimed.kind, claimed.payload).await {
+                Ok(()) => {
+                    debug!("Job succeeded");
+                    job::complete(pool, claimed.id).await
+                }
+                Err(err) if claimed.attempts >= claimed.max_attempts => {
+                    error!("Job failed for good: {err:#}");
+                    job::fail(pool, claimed.id, &format!("{err:#}")).await
+                }
+                Err(err) => {
+                    let delay = backoff(claimed.attempts);
+                    warn!(retry_in_secs = delay.as_secs(), "Job failed: {err:#}");
+                    job::retry(pool, claimed.id, &format!("{err:#}"), delay).await
+                }
+            }
+        }
+        .instrument(span)
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Run a job of `kind` with the JSON `payload`.
+    async fn dispatch(&self, kind: &str, payload: String) -> anyhow::Result<()> {
+        let Some(handler) = self.handlers.get(kind) else {
+            anyhow::bail!("No handler for jobs of kind '{kind}'");
+        };
+
+        // On a task of its own, so a panicking job fails rather than taking the worker down.
+        tokio::spawn(handler(self.context.clone(), payload).in_current_span())
+            .await
+            .map_err(|err| anyhow::anyhow!("Job panicked: {err}"))?
+    }
+}
+
+/// Deletes expired authorization codes, forgotten login throttles and old failed jobs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cleanup;
+
+#[async_trait]
+impl Job for Cleanup {
+    const KIND: &'static str = "cleanup";
+
+    async fn run(self, context: &JobContext) -> anyhow::Result<()> {
+        let pool = &context.pool;
+
+        let codes = oauth2::delete_expired_codes(pool).await?;
+        let throttles = login_throttle::delete_stale(pool).await?;
+        let jobs = job::delete_failed(pool, FAILED_JOB_RETENTION).await?;
+
+        info!(codes, throttles, jobs, "Cleaned up");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use sqlx::postgres::PgPoolOptions;
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::mail::LogMailer;
+
+    static ADDED: AtomicU64 = AtomicU64::new(0);
+
+    #[derive(Serialize, Deserialize)]
+    struct Add(u64);
+
+    #[async_trait]
+    impl Job for Add {
+        const KIND: &'static str = "add";
+
+        async fn run(self, _context: &JobContext) -> anyhow::Result<()> {
+            ADDED.fetch_add(self.0, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Panic;
+
+    #[async_trait]
+    impl Job for Panic {
+        const KIND: &'static str = "panic";
+
+        async fn run(self, _context: &JobContext) -> anyhow::Result<()> {
+            panic!("Oops");
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(10));
+        assert_eq!(backoff(2), Duration::from_secs(20));
+        assert_eq!(backoff(4), Duration::from_secs(80));
+
+        // Capped, even when the exponent overflows.
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(i32::MAX), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_dispatch() {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://aurora@127.0.0.1:1/aurora")
+            .unwrap();
+        let worker = Worker::new(JobContext {
+            pool,
+            mailer: Arc::new(LogMailer),
+        })
+        .register::<Add>()
+        .register::<Panic>();
+
+        let payload = serde_json::to_string(&Add(42)).unwrap();
+        worker.dispatch(Add::KIND, payload).await.unwrap();
+        assert_eq!(ADDED.load(Ordering::Relaxed), 42);
+
+        assert!(worker.dispatch(Add::KIND, "\"42\"".into()).await.is_err());
+        assert!(worker.dispatch("unknown", "null".into()).await.is_err());
+        assert!(worker.dispatch(Panic::KIND, "null".into()).await.is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! The Aurora backend, shared by the `aurora-api` server and the `aurora-worker` binary.
+
+pub mod health;
+pub mod jobs;
+pub mod mail;
+pub mod metrics;
+pub mod models;
+pub mod request_id;
+pub mod security;
+pub mod telemetry;
+pub mod tls;
+pub mod v1;
+
+#[macro_use]
+extern crate tracing;
+
+#[macro_use]
+extern crate lazy_static;
+
+/// Environment variables both `aurora-api` and `aurora-worker` can't do without.
+const REQUIRED_ENV_VARS: [&str; 3] = ["DATABASE_URL", "HMAC_SECURITY_KEY", "TOKEN_EXPIRATION_TIME"];
+
+/// Fail unless every one of [REQUIRED_ENV_VARS] is set (and not empty), before anything
+/// depends on them.
+///
+/// Tokens read theirs lazily, so a missing one would otherwise only show once a request
+/// or job needs a token, and fail that.
+pub fn require_env() -> anyhow::Result<()> {
+    for var in REQUIRED_ENV_VARS {
+        if std::env::var(var).map_or(true, |value| value.is_empty()) {
+            anyhow::bail!("{var} environment variable must be set");
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves once the process is asked to terminate (SIGINT or SIGTERM).
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -1,65 +1,256 @@
-use axum::{routing::get, Router};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use aurora_api::{
+    health,
+    jobs::{self, JobContext},
+    mail, metrics, models, request_id, require_env, security, shutdown_signal, telemetry, tls, v1,
+};
+use axum::{http::HeaderValue, middleware, routing::get, Router};
 use const_format::formatcp;
 use dotenv::dotenv;
-use sqlx::postgres::PgPoolOptions;
-use tokio::time::Duration;
-use tower_http::trace::TraceLayer;
-
-mod v1;
+use sqlx::PgPool;
+use tokio::{sync::watch, time::Duration};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
 #[macro_use]
 extern crate tracing;
 
-#[macro_use]
-extern crate lazy_static;
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv()?;
 
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::TRACE)
-        .init();
+    let telemetry = telemetry::init()?;
 
     //
     // Validate Environment Variables
     //
-    let required_env_vars = vec!["DATABASE_URL", "HMAC_SECURITY_KEY", "TOKEN_EXPIRATION_TIME"];
-    for env_var in required_env_vars {
-        if std::env::var(env_var).is_err() {
-            panic!("{} environment variable must be set", env_var);
-        }
+    require_env()?;
+
+    // Time in seconds in-flight requests get to finish once a shutdown was requested.
+    let shutdown_timeout = Duration::from_secs(
+        std::env::var("SHUTDOWN_TIMEOUT")
+            .map(|timeout| {
+                timeout
+                    .parse()
+                    .expect("SHUTDOWN_TIMEOUT must be a valid integer")
+            })
+            .unwrap_or(30),
+    );
+
+    let pool = models::connect().await?;
+    models::migrate(&pool).await?;
+
+    let metrics_token = metrics::token_from_env();
+    let serves_metrics = metrics_token.is_some();
+    if !serves_metrics {
+        info!("Not serving /metrics, METRICS_TOKEN is not set");
     }
 
+    let app = app(
+        pool.clone(),
+        security::allowed_origins_from_env(),
+        metrics_token,
+    );
+
     //
-    // Database Connection
+    // Background jobs, see [jobs] for how to configure them.
     //
-    let db_connection_str = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let (stop_worker, worker_shutdown) = watch::channel(false);
+    let worker = if jobs::inline_from_env() {
+        let context = JobContext {
+            pool: pool.clone(),
+            mailer: mail::from_env()?,
+        };
+        Some(tokio::spawn(
+            jobs::worker(context).run(jobs::concurrency_from_env(), worker_shutdown),
+        ))
+    } else {
+        info!("Not running jobs, WORKER is off");
+        None
+    };
 
-    info!("Connecting to database...");
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .acquire_timeout(Duration::from_secs(3))
-        .connect(&db_connection_str)
-        .await?;
-
-    let app = Router::new() //
-        .route("/", get(root))
-        .nest("/api/v1", v1::register_routes())
-        .with_state(pool)
-        .layer(TraceLayer::new_for_http());
+    //
+    // TLS, see [tls] for how to configure it.
+    //
+    let tls = match tls::TlsPaths::from_env() {
+        Some(paths) => {
+            let config = tls::config(&paths).await?;
+            tokio::spawn(tls::watch(config.clone(), paths));
+            Some(config)
+        }
+        None => None,
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
 
     info!("listening on :3000 :: {:#?}", root().await);
     info!("Available routes:");
-    info!("  http://localhost:3000/");
-    info!("  http://localhost:3000/api/v1/auth/login");
+    info!("  {scheme}://localhost:3000/");
+    info!("  {scheme}://localhost:3000/healthz");
+    info!("  {scheme}://localhost:3000/readyz");
+    info!("  {scheme}://localhost:3000/version");
+    if serves_metrics {
+        info!("  {scheme}://localhost:3000/metrics");
+    }
+    info!("  {scheme}://localhost:3000/api/v1/auth/login");
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    //
+    // Serve until SIGINT/SIGTERM, then drain open connections.
+    //
+    let handle = axum_server::Handle::new();
+    let mut server: Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>> = match tls {
+        Some(config) => Box::pin(
+            axum_server::bind_rustls(addr, config)
+                .handle(handle.clone())
+                .serve(make_service),
+        ),
+        None => Box::pin(
+            axum_server::bind(addr)
+                .handle(handle.clone())
+                .serve(make_service),
+        ),
+    };
+
+    tokio::select! {
+        result = &mut server => result?,
+        _ = shutdown_signal() => {
+            info!("Shutting down, draining connections for up to {shutdown_timeout:?}...");
+            handle.graceful_shutdown(None);
+
+            match tokio::time::timeout(shutdown_timeout, server).await {
+                Ok(result) => result?,
+                Err(_) => warn!("Connections did not drain in time, dropping them."),
+            }
+        }
+    }
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await?;
+    if let Some(worker) = worker {
+        info!("Waiting for running jobs...");
+        stop_worker.send_replace(true);
+        worker.await?;
+    }
+
+    info!("Closing database connections...");
+    pool.close().await;
+
+    telemetry.shutdown().await;
 
     Ok(())
 }
 
+/// Every route of the API, with all of its layers.
+fn app(pool: PgPool, allowed_origins: Vec<HeaderValue>, metrics_token: Option<String>) -> Router {
+    Router::new() //
+        .route("/", get(root))
+        .merge(health::register_routes())
+        .merge(metrics::register_routes(metrics_token))
+        .nest("/api/v1", v1::register_routes(pool.clone()))
+        .with_state(pool)
+        .layer(middleware::from_fn(metrics::track_requests))
+        .layer(middleware::from_fn(request_id::scope_request_id))
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::make_span))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .layer(middleware::from_fn(security::security_headers))
+        .layer(security::cors_layer(allowed_origins))
+}
+
 async fn root() -> &'static str {
     formatcp!("aurora-api@{}", env!("CARGO_PKG_VERSION"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+    use tracing_test::traced_test;
+
+    const WEB_ORIGIN: &str = "http://localhost:8080";
+
+    fn test_app() -> Router {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://aurora@127.0.0.1:1/aurora")
+            .unwrap();
+
+        app(pool, vec![HeaderValue::from_static(WEB_ORIGIN)], None)
+    }
+
+    fn preflight(origin: &str) -> Request<Body> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/auth/login")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_preflight_allowed_origin() {
+        let response = test_app().oneshot(preflight(WEB_ORIGIN)).await.unwrap();
+        let headers = response.headers();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], WEB_ORIGIN);
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap()
+            .contains("POST"));
+        assert!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap()
+            .contains("content-type"));
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_preflight_unknown_origin() {
+        let response = test_app()
+            .oneshot(preflight("https://evil.example"))
+            .await
+            .unwrap();
+
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_security_headers() {
+        let response = test_app()
+            .oneshot(
+                Request::get("/healthz")
+                    .header(header::ORIGIN, WEB_ORIGIN)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let headers = response.headers();
+
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert!(headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], WEB_ORIGIN);
+        assert!(headers[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap()
+            .contains("x-request-id"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Prometheus metrics, served on `/metrics`.
+//!
+//! They tell a lot about the deployment, so scrapers have to authenticate with
+//! `Authorization: Bearer <METRICS_TOKEN>`. Without `METRICS_TOKEN`, `/metrics` isn't served.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use tokio::time::Instant;
+
+use crate::v1::{error::APIError, token::TokenError};
+
+lazy_static! {
+    /// Amount of handled HTTP requests.
+    static ref HTTP_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "aurora_http_requests_total",
+        "Amount of handled HTTP requests.",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+
+    /// Time it took to handle HTTP requests.
+    static ref HTTP_REQUEST_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
+        "aurora_http_request_duration_seconds",
+        "Time it took to handle HTTP requests.",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+
+    /// Connections of the database pool, by their state.
+    static ref DB_POOL_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "aurora_db_pool_connections",
+        "Connections of the database pool, by their state.",
+        &["state"]
+    )
+    .unwrap();
+
+    /// Tokens which failed verification, by the [TokenError] they failed with.
+    static ref TOKEN_VERIFICATION_FAILURES_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "aurora_token_verification_failures_total",
+        "Tokens which failed verification, by the error they failed with.",
+        &["error"]
+    )
+    .unwrap();
+}
+
+/// Read the token scrapers authenticate with from `METRICS_TOKEN`.
+pub fn token_from_env() -> Option<String> {
+    std::env::var("METRICS_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// `/metrics`, for those presenting `token`. Nothing is served without one.
+pub fn register_routes(token: Option<String>) -> Router<PgPool> {
+    let Some(token) = token else {
+        return Router::new();
+    };
+
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            require_token,
+        ))
+}
+
+/// Middleware rejecting requests without `Authorization: Bearer <token>`.
+async fn require_token(State(token): State<Arc<str>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|sent| sent.as_bytes().ct_eq(token.as_bytes()).into());
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Record a token which failed verification with `err`, other errors are ignored.
+pub fn record_token_failure(err: &APIError) {
+    let error = match err {
+        APIError::InvalidToken(err) => format!("{err:?}"),
+        APIError::ExpiredToken => format!("{:?}", TokenError::Expired),
+        _ => return,
+    };
+
+    TOKEN_VERIFICATION_FAILURES_TOTAL
+        .with_label_values(&[&error])
+        .inc();
+}
+
+/// Middleware recording the count and latency of requests, use it with [axum::middleware::from_fn].
+///
+/// Requests are labeled by their route instead of their path, to keep the cardinality low.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".into());
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+
+    HTTP_REQUESTS_TOTAL.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// GET /metrics - metrics in the Prometheus text format.
+pub async fn get_metrics(State(pool): State<PgPool>) -> Response {
+    let idle = pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["active"])
+        .set(pool.size() as i64 - idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["max"])
+        .set(pool.options().get_max_connections() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {err}");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_owned())],
+        buffer,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use sqlx::postgres::PgPoolOptions;
+    use tower::ServiceExt;
+    use tracing_test::traced_test;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_metrics() {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://aurora@127.0.0.1:1/aurora")
+            .unwrap();
+
+        let app = Router::new()
+            .route("/ping/:id", get(|| async { "pong" }))
+            .merge(register_routes(Some("scraper-token".into())))
+            .layer(middleware::from_fn(track_requests))
+            .with_state(pool);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/ping/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        record_token_failure(&APIError::InvalidToken(TokenError::HmacVerification));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(
+                Request::get("/metrics")
+                    .header(header::AUTHORIZATION, "Bearer scraper-token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains(
+            r#"aurora_http_requests_total{method="GET",route="/ping/:id",status="200"} 1"#
+        ));
+        assert!(body.contains(
+            r#"aurora_http_request_duration_seconds_bucket{method="GET",route="/ping/:id""#
+        ));
+        assert!(
+            body.contains(r#"aurora_token_verification_failures_total{error="HmacVerification"}"#)
+        );
+        assert!(body.contains(r#"aurora_db_pool_connections{state="max"} 10"#));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_metrics_without_token() {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://aurora@127.0.0.1:1/aurora")
+            .unwrap();
+        let app = register_routes(None).with_state(pool);
+
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,142 @@
+use sqlx::PgPool;
+use tokio::time::Duration;
+
+/// A job claimed by a worker, see [claim].
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ClaimedJob {
+    pub id: i64,
+    pub kind: String,
+    /// The JSON payload, as text.
+    pub payload: String,
+    /// Attempts so far, this one included.
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+/// Add a job of `kind`, to run after `delay`.
+pub async fn enqueue(
+    pool: &PgPool,
+    kind: &str,
+    payload: &str,
+    max_attempts: i32,
+    delay: Duration,
+) -> sqlx::Result<i64> {
+    sqlx::query_scalar(
+        "INSERT INTO jobs (kind, payload, max_attempts, run_at)
+         VALUES ($1, $2::jsonb, $3, now() + make_interval(secs => $4))
+         RETURNING id",
+    )
+    .bind(kind)
+    .bind(payload)
+    .bind(max_attempts)
+    .bind(delay.as_secs_f64())
+    .fetch_one(pool)
+    .await
+}
+
+/// Claim the next due job for `lease`, counting it as an attempt.
+///
+/// Jobs locked by other workers are skipped rather than waited for, so any amount of
+/// workers can poll the same table.
+pub async fn claim(pool: &PgPool, lease: Duration) -> sqlx::Result<Option<ClaimedJob>> {
+    sqlx::query_as(
+        "UPDATE jobs SET attempts = attempts + 1,
+                         locked_until = now() + make_interval(secs => $1)
+         WHERE id = (
+            SELECT id FROM jobs
+            WHERE failed_at IS NULL AND run_at <= now()
+              AND (locked_until IS NULL OR locked_until < now())
+            ORDER BY run_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+         )
+         RETURNING id, kind, payload::text AS payload, attempts, max_attempts",
+    )
+    .bind(lease.as_secs_f64())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Delete a job which succeeded.
+pub async fn complete(pool: &PgPool, id: i64) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM jobs WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Release a job which failed, to be attempted again after `delay`.
+pub async fn retry(pool: &PgPool, id: i64, error: &str, delay: Duration) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE jobs SET locked_until = NULL, last_error = $2,
+                         run_at = now() + make_interval(secs => $3)
+         WHERE id = $1",
+    )
+    .bind(id)
+    .bind(error)
+    .bind(delay.as_secs_f64())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Give up on a job which failed its last attempt.
+pub async fn fail(pool: &PgPool, id: i64, error: &str) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE jobs SET locked_until = NULL, last_error = $2, failed_at = now() WHERE id = $1",
+    )
+    .bind(id)
+    .bind(error)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete jobs which failed more than `retention` ago.
+pub async fn delete_failed(pool: &PgPool, retention: Duration) -> sqlx::Result<u64> {
+    let result =
+        sqlx::query("DELETE FROM jobs WHERE failed_at < now() - make_interval(secs => $1)")
+            .bind(retention.as_secs_f64())
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Enqueue the job of the schedule `name` if it is due, and move the schedule `interval` ahead.
+///
+/// A schedule seen for the first time is due right away. Returns whether a job was enqueued,
+/// which happens for one worker only.
+pub async fn enqueue_scheduled(
+    pool: &PgPool,
+    name: &str,
+    kind: &str,
+    payload: &str,
+    max_attempts: i32,
+    interval: Duration,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        "WITH due AS (
+            INSERT INTO job_schedules (name, next_run_at)
+            VALUES ($1, now() + make_interval(secs => $5))
+            ON CONFLICT (name) DO UPDATE SET next_run_at = EXCLUDED.next_run_at
+            WHERE job_schedules.next_run_at <= now()
+            RETURNING name
+         )
+         INSERT INTO jobs (kind, payload, max_attempts)
+         SELECT $2, $3::jsonb, $4 FROM due",
+    )
+    .bind(name)
+    .bind(kind)
+    .bind(payload)
+    .bind(max_attempts)
+    .bind(interval.as_secs_f64())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
@@ -0,0 +1,184 @@
+use std::net::IpAddr;
+
+use sqlx::PgPool;
+use tokio::time::Duration;
+
+/// Failed logins are forgotten once nothing failed for this long.
+const RESET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// The cooldown of the first throttled attempt, doubled for every further failure.
+const BASE_COOLDOWN: Duration = Duration::from_secs(1);
+
+/// Upper bound of the cooldown, reaching it means the key is locked out.
+const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+
+/// What failed login attempts are tracked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleScope {
+    /// The (lowercased) login name, whether the account exists or not.
+    Account,
+
+    /// The IP address the attempt came from.
+    Ip,
+}
+
+impl ThrottleScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Account => "account",
+            Self::Ip => "ip",
+        }
+    }
+
+    /// Amount of failures tolerated before any cooldown kicks in.
+    ///
+    /// IPs are shared (NAT, offices, ...), so they get more slack than a single account.
+    fn free_attempts(&self) -> u32 {
+        match self {
+            Self::Account => 3,
+            Self::Ip => 20,
+        }
+    }
+
+    /// The cooldown after `failures` consecutive failures.
+    pub fn cooldown(&self, failures: u32) -> Duration {
+        let throttled = failures.saturating_sub(self.free_attempts());
+        if throttled == 0 {
+            return Duration::ZERO;
+        }
+
+        BASE_COOLDOWN
+            .checked_mul(2u32.saturating_pow(throttled - 1))
+            .map_or(LOCKOUT_DURATION, |cooldown| cooldown.min(LOCKOUT_DURATION))
+    }
+}
+
+/// Seconds until both the account and the IP are allowed to attempt a login again.
+/// `None` if neither is cooling down.
+pub async fn cooldown(pool: &PgPool, login: &str, ip: IpAddr) -> sqlx::Result<Option<u64>> {
+    let seconds: Option<f64> = sqlx::query_scalar(
+        "SELECT EXTRACT(EPOCH FROM max(locked_until) - now())::float8 FROM login_throttles
+         WHERE ((scope = $1 AND key = $2) OR (scope = $3 AND key = $4)) AND locked_until > now()",
+    )
+    .bind(ThrottleScope::Account.as_str())
+    .bind(login)
+    .bind(ThrottleScope::Ip.as_str())
+    .bind(ip.to_string())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(seconds.map(|seconds| seconds.ceil() as u64))
+}
+
+/// Record a failed login attempt for both the account and the IP.
+pub async fn record_failure(pool: &PgPool, login: &str, ip: IpAddr) -> sqlx::Result<()> {
+    for (scope, key) in [
+        (ThrottleScope::Account, login.to_owned()),
+        (ThrottleScope::Ip, ip.to_string()),
+    ] {
+        let failures: i32 = sqlx::query_scalar(
+            "INSERT INTO login_throttles (scope, key, failures, last_failure_at)
+             VALUES ($1, $2, 1, now())
+             ON CONFLICT (scope, key) DO UPDATE SET
+                failures = CASE
+                    WHEN login_throttles.last_failure_at < now() - $3::float8 * interval '1 second' THEN 1
+                    ELSE login_throttles.failures + 1
+                END,
+                last_failure_at = now()
+             RETURNING failures",
+        )
+        .bind(scope.as_str())
+        .bind(&key)
+        .bind(RESET_AFTER.as_secs_f64())
+        .fetch_one(pool)
+        .await?;
+
+        let failures = failures as u32;
+        let cooldown = scope.cooldown(failures);
+        if cooldown.is_zero() {
+            continue;
+        }
+
+        sqlx::query(
+            "UPDATE login_throttles SET locked_until = now() + $3::float8 * interval '1 second'
+             WHERE scope = $1 AND key = $2",
+        )
+        .bind(scope.as_str())
+        .bind(&key)
+        .bind(cooldown.as_secs_f64())
+        .execute(pool)
+        .await?;
+
+        if cooldown >= LOCKOUT_DURATION {
+            warn!(
+                scope = scope.as_str(),
+                key,
+                failures,
+                lockout_secs = cooldown.as_secs(),
+                "Login locked out after repeated failures"
+            );
+        } else {
+            warn!(
+                scope = scope.as_str(),
+                key,
+                failures,
+                cooldown_secs = cooldown.as_secs(),
+                "Login throttled after repeated failures"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Forget the failed attempts of an account, after it logged in successfully.
+///
+/// The IP is left alone, a single valid account must not wipe out
+/// the failures it racked up against other accounts.
+pub async fn reset(pool: &PgPool, login: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM login_throttles WHERE scope = $1 AND key = $2")
+        .bind(ThrottleScope::Account.as_str())
+        .bind(login)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Delete throttles which are forgotten anyway, and not cooling down.
+pub async fn delete_stale(pool: &PgPool) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM login_throttles
+         WHERE last_failure_at < now() - $1::float8 * interval '1 second'
+           AND (locked_until IS NULL OR locked_until <= now())",
+    )
+    .bind(RESET_AFTER.as_secs_f64())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown_backoff() {
+        let scope = ThrottleScope::Account;
+
+        assert_eq!(scope.cooldown(0), Duration::ZERO);
+        assert_eq!(scope.cooldown(3), Duration::ZERO);
+        assert_eq!(scope.cooldown(4), Duration::from_secs(1));
+        assert_eq!(scope.cooldown(5), Duration::from_secs(2));
+        assert_eq!(scope.cooldown(8), Duration::from_secs(16));
+
+        // Capped at the lockout duration, even when the exponent overflows.
+        assert_eq!(scope.cooldown(20), LOCKOUT_DURATION);
+        assert_eq!(scope.cooldown(u32::MAX), LOCKOUT_DURATION);
+
+        // IPs get more slack.
+        assert_eq!(ThrottleScope::Ip.cooldown(20), Duration::ZERO);
+        assert_eq!(ThrottleScope::Ip.cooldown(21), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,40 @@
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, PgPool};
+use tokio::time::Duration;
+
+pub mod job;
+pub mod login_throttle;
+pub mod oauth2;
+pub mod user;
+
+/// Migrations embedded from `migrations/`, applied on startup.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Connect to `DATABASE_URL`.
+pub async fn connect() -> anyhow::Result<PgPool> {
+    let db_connection_str = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+
+    info!("Connecting to database...");
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .acquire_timeout(Duration::from_secs(3))
+        .connect(&db_connection_str)
+        .await?;
+
+    Ok(pool)
+}
+
+/// Apply pending [MIGRATOR] migrations.
+pub async fn migrate(pool: &PgPool) -> anyhow::Result<()> {
+    info!("Running migrations...");
+    MIGRATOR.run(pool).await?;
+
+    Ok(())
+}
+
+/// Milliseconds since the unix epoch, like tokens carry them. Bound as `to_timestamp($n / 1000)`.
+///
+/// Times compared against tokens come from the clock of the API which generates the tokens,
+/// never from `now()` of the database, which may be skewed against it.
+pub(crate) fn unix_millis(time: time::OffsetDateTime) -> f64 {
+    (time.unix_timestamp_nanos() / 1_000_000) as f64
+}
@@ -0,0 +1,247 @@
+use sqlx::{PgExecutor, PgPool};
+
+/// How long an authorization code can be exchanged, in seconds.
+const CODE_LIFETIME: i64 = 10 * 60;
+
+/// An application registered as OAuth2 client, see [crate::v1::oauth2].
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Client {
+    /// The ID of the application, which is its client ID.
+    pub application_id: i64,
+
+    /// The URIs users may be sent back to, compared exactly.
+    pub redirect_uris: Vec<String>,
+
+    /// Hash of the client secret, `None` for public clients.
+    pub secret_hash: Option<String>,
+}
+
+impl Client {
+    pub async fn find(pool: &PgPool, application_id: i64) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as(
+            "SELECT application_id, redirect_uris, secret_hash FROM oauth2_clients
+             WHERE application_id = $1",
+        )
+        .bind(application_id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Register the application as client, or update its registration.
+    pub async fn save(
+        pool: &PgPool,
+        application_id: i64,
+        redirect_uris: &[String],
+        secret_hash: Option<&str>,
+    ) -> sqlx::Result<Self> {
+        sqlx::query_as(
+            "INSERT INTO oauth2_clients (application_id, redirect_uris, secret_hash)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (application_id)
+             DO UPDATE SET redirect_uris = EXCLUDED.redirect_uris, secret_hash = EXCLUDED.secret_hash
+             RETURNING application_id, redirect_uris, secret_hash",
+        )
+        .bind(application_id)
+        .bind(redirect_uris)
+        .bind(secret_hash)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+}
+
+/// An authorization code, as taken by [take_code].
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AuthorizationCode {
+    pub application_id: i64,
+    pub user_id: i64,
+    pub scope: String,
+    pub redirect_uri: Option<String>,
+    pub code_challenge: String,
+    pub expired: bool,
+}
+
+/// Store a code for `user_id` authorizing `application_id`, valid for [CODE_LIFETIME].
+pub async fn create_code(
+    pool: &PgPool,
+    code_hash: &str,
+    application_id: i64,
+    user_id: i64,
+    scope: &str,
+    redirect_uri: Option<&str>,
+    code_challenge: &str,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO oauth2_codes
+            (code_hash, application_id, user_id, scope, redirect_uri, code_challenge, expires_at)
+         VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7))",
+    )
+    .bind(code_hash)
+    .bind(application_id)
+    .bind(user_id)
+    .bind(scope)
+    .bind(redirect_uri)
+    .bind(code_challenge)
+    .bind(CODE_LIFETIME as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete and return the code with the hash `code_hash` issued to `application_id`, so it
+/// can be used once only.
+pub async fn take_code(
+    pool: &PgPool,
+    code_hash: &str,
+    application_id: i64,
+) -> sqlx::Result<Option<AuthorizationCode>> {
+    sqlx::query_as(
+        "DELETE FROM oauth2_codes WHERE code_hash = $1 AND application_id = $2
+         RETURNING application_id, user_id, scope, redirect_uri, code_challenge,
+                   expires_at <= now() AS expired",
+    )
+    .bind(code_hash)
+    .bind(application_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Delete codes which expired without being exchanged.
+pub async fn delete_expired_codes(pool: &PgPool) -> sqlx::Result<u64> {
+    let result = sqlx::query("DELETE FROM oauth2_codes WHERE expires_at <= now()")
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Record that `user_id` authorized `application_id`, with a new refresh token.
+///
+/// Authorizing again replaces the scope, and revokes the access tokens issued until now,
+/// as they may carry scopes which are no longer granted.
+pub async fn authorize(
+    pool: &PgPool,
+    user_id: i64,
+    application_id: i64,
+    scope: &str,
+    refresh_token_hash: &str,
+) -> sqlx::Result<()> {
+    // Like [super::user::User::set_password], compared against the generation time of tokens.
+    sqlx::query(
+        "INSERT INTO oauth2_authorizations (user_id, application_id, scope, refresh_token_hash, created_at)
+         VALUES ($1, $2, $3, $4, to_timestamp($5 / 1000))
+         ON CONFLICT (user_id, application_id)
+         DO UPDATE SET scope = EXCLUDED.scope, refresh_token_hash = EXCLUDED.refresh_token_hash,
+                       created_at = EXCLUDED.created_at",
+    )
+    .bind(user_id)
+    .bind(application_id)
+    .bind(scope)
+    .bind(refresh_token_hash)
+    .bind(super::unix_millis(time::OffsetDateTime::now_utc()))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The user ID and scope of the authorization of `application_id` with the given refresh token.
+pub async fn find_by_refresh_token(
+    pool: &PgPool,
+    application_id: i64,
+    refresh_token_hash: &str,
+) -> sqlx::Result<Option<(i64, String)>> {
+    sqlx::query_as(
+        "SELECT user_id, scope FROM oauth2_authorizations
+         WHERE application_id = $1 AND refresh_token_hash = $2",
+    )
+    .bind(application_id)
+    .bind(refresh_token_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Replace a refresh token of `application_id`.
+///
+/// Returns `false` if the refresh token is unknown, e.g. because it was just used.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    application_id: i64,
+    refresh_token_hash: &str,
+    new_refresh_token_hash: &str,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE oauth2_authorizations SET refresh_token_hash = $3
+         WHERE application_id = $1 AND refresh_token_hash = $2",
+    )
+    .bind(application_id)
+    .bind(refresh_token_hash)
+    .bind(new_refresh_token_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Delete the authorization of `application_id` by `user_id`, revoking all of its tokens.
+pub async fn revoke(pool: &PgPool, user_id: i64, application_id: i64) -> sqlx::Result<bool> {
+    let result =
+        sqlx::query("DELETE FROM oauth2_authorizations WHERE user_id = $1 AND application_id = $2")
+            .bind(user_id)
+            .bind(application_id)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Delete every authorization of `user_id`, revoking all of their access and refresh tokens.
+pub async fn revoke_all(executor: impl PgExecutor<'_>, user_id: i64) -> sqlx::Result<u64> {
+    let result = sqlx::query("DELETE FROM oauth2_authorizations WHERE user_id = $1")
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Delete the authorization of `application_id` with the given refresh token.
+pub async fn revoke_by_refresh_token(
+    pool: &PgPool,
+    application_id: i64,
+    refresh_token_hash: &str,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        "DELETE FROM oauth2_authorizations WHERE application_id = $1 AND refresh_token_hash = $2",
+    )
+    .bind(application_id)
+    .bind(refresh_token_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// The scope of the authorization of `application_id` by `user_id` backing an access token
+/// generated at `generated_at`, or `None` if it was revoked or replaced since.
+pub async fn authorized_scope(
+    pool: &PgPool,
+    user_id: i64,
+    application_id: i64,
+    generated_at: time::OffsetDateTime,
+) -> sqlx::Result<Option<String>> {
+    // Like [super::user::session_status], tokens carry milliseconds.
+    sqlx::query_scalar(
+        "SELECT scope FROM oauth2_authorizations
+         WHERE user_id = $1 AND application_id = $2 AND created_at <= to_timestamp($3 / 1000)",
+    )
+    .bind(user_id)
+    .bind(application_id)
+    .bind(super::unix_millis(generated_at))
+    .fetch_optional(pool)
+    .await
+}
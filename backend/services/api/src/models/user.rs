@@ -0,0 +1,271 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use const_format::concatcp;
+use sqlx::PgPool;
+
+lazy_static! {
+    /// Hash used to verify passwords against when the requested user does not exist.
+    /// So the response time does not tell whether an account exists.
+    static ref DUMMY_PASSWORD_HASH: String =
+        hash_password("aurora-dummy-password").expect("Failed to hash dummy password");
+}
+
+/// Columns selected into a [User].
+const COLUMNS: &str = "id, username, password_hash, email, \
+    email_verified_at IS NOT NULL AS email_verified, owner_id, owner_id IS NOT NULL AS bot";
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub password_hash: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+
+    /// The user who created this bot, see [User::create_bot].
+    pub owner_id: Option<i64>,
+    pub bot: bool,
+}
+
+impl User {
+    /// Find a user by their ID.
+    pub async fn find_by_id(pool: &PgPool, id: i64) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as(concatcp!("SELECT ", COLUMNS, " FROM users WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Find a user by their username, ignoring casing.
+    pub async fn find_by_username(pool: &PgPool, username: &str) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as(concatcp!(
+            "SELECT ",
+            COLUMNS,
+            " FROM users WHERE lower(username) = lower($1)"
+        ))
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Find a user by their email, ignoring casing.
+    pub async fn find_by_email(pool: &PgPool, email: &str) -> sqlx::Result<Option<Self>> {
+        sqlx::query_as(concatcp!(
+            "SELECT ",
+            COLUMNS,
+            " FROM users WHERE lower(email) = lower($1)"
+        ))
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Create a user with an unverified email.
+    ///
+    /// Fails with a unique violation if the username or email is taken, see [taken_field].
+    pub async fn create(
+        pool: &PgPool,
+        username: &str,
+        email: Option<&str>,
+        password_hash: &str,
+    ) -> sqlx::Result<Self> {
+        sqlx::query_as(concatcp!(
+            "INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3) RETURNING ",
+            COLUMNS
+        ))
+        .bind(username)
+        .bind(email)
+        .bind(password_hash)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Create a bot owned by `owner_id`.
+    ///
+    /// Bots can't log in, they get a token when created and whenever it is regenerated.
+    pub async fn create_bot(pool: &PgPool, owner_id: i64, username: &str) -> sqlx::Result<Self> {
+        sqlx::query_as(concatcp!(
+            "INSERT INTO users (username, password_hash, owner_id) VALUES ($1, '', $2) RETURNING ",
+            COLUMNS
+        ))
+        .bind(username)
+        .bind(owner_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// The bots owned by `owner_id`, oldest first.
+    pub async fn find_bots(pool: &PgPool, owner_id: i64) -> sqlx::Result<Vec<Self>> {
+        sqlx::query_as(concatcp!(
+            "SELECT ",
+            COLUMNS,
+            " FROM users WHERE owner_id = $1 ORDER BY id"
+        ))
+        .bind(owner_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Revoke every token issued to the user until now.
+    pub async fn revoke_sessions(&self, pool: &PgPool) -> sqlx::Result<()> {
+        // Like in [User::set_password].
+        sqlx::query(
+            "UPDATE users SET sessions_valid_after = to_timestamp($2 / 1000) WHERE id = $1",
+        )
+        .bind(self.id)
+        .bind(super::unix_millis(time::OffsetDateTime::now_utc()))
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Mark the email of the user as verified.
+    ///
+    /// Returns `false` if it was verified already.
+    pub async fn verify_email(&self, pool: &PgPool) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE users SET email_verified_at = now() WHERE id = $1 AND email_verified_at IS NULL",
+        )
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Replace the password of the user, and revoke every token issued until now.
+    ///
+    /// That includes the authorizations of OAuth2 applications, which could otherwise
+    /// keep refreshing their access tokens.
+    pub async fn set_password(&self, pool: &PgPool, password_hash: &str) -> sqlx::Result<()> {
+        let mut transaction = pool.begin().await?;
+
+        // Tokens carry milliseconds, anything finer would reject tokens issued right after.
+        sqlx::query(
+            "UPDATE users SET password_hash = $2, sessions_valid_after = to_timestamp($3 / 1000)
+             WHERE id = $1",
+        )
+        .bind(self.id)
+        .bind(password_hash)
+        .bind(super::unix_millis(time::OffsetDateTime::now_utc()))
+        .execute(&mut *transaction)
+        .await?;
+
+        super::oauth2::revoke_all(&mut *transaction, self.id).await?;
+
+        transaction.commit().await
+    }
+
+    /// Reset the password of the user, like [User::set_password], and unlock their logins.
+    ///
+    /// A locked out owner is the typical reason for a reset.
+    pub async fn reset_password(&self, pool: &PgPool, password_hash: &str) -> sqlx::Result<()> {
+        self.set_password(pool, password_hash).await?;
+        super::login_throttle::reset(pool, &self.username.to_lowercase()).await
+    }
+}
+
+/// What a token of a user is good for, see [session_status].
+#[derive(Debug, Clone, Copy, sqlx::FromRow)]
+pub struct SessionStatus {
+    /// The token was not revoked.
+    pub valid: bool,
+    pub bot: bool,
+}
+
+/// Whether a token generated at `generated_at` is still accepted for the user `id`,
+/// or `None` if there is no such user.
+pub async fn session_status(
+    pool: &PgPool,
+    id: i64,
+    generated_at: time::OffsetDateTime,
+) -> sqlx::Result<Option<SessionStatus>> {
+    sqlx::query_as(
+        "SELECT sessions_valid_after IS NULL OR sessions_valid_after <= to_timestamp($2 / 1000) AS valid,
+                owner_id IS NOT NULL AS bot
+         FROM users WHERE id = $1",
+    )
+    .bind(id)
+    .bind(super::unix_millis(generated_at))
+    .fetch_optional(pool)
+    .await
+}
+
+/// The field a unique violation from [User::create] is about, if it is one.
+pub fn taken_field(err: &sqlx::Error) -> Option<&'static str> {
+    let err = err.as_database_error()?;
+    if !err.is_unique_violation() {
+        return None;
+    }
+
+    match err.constraint()? {
+        "users_username_key" => Some("username"),
+        "users_email_key" => Some("email"),
+        _ => None,
+    }
+}
+
+/// Hash a password using Argon2id with a random salt.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Verify a password against the hash of `user`.
+///
+/// If no user is given, the password is verified against a dummy hash
+/// and `false` is returned, to keep the timing of both cases similar.
+///
+/// Hashing is expensive, so it is done on the blocking thread pool.
+pub async fn verify_password(user: Option<&User>, password: String) -> bool {
+    let hash = match user {
+        Some(user) => user.password_hash.clone(),
+        None => DUMMY_PASSWORD_HASH.clone(),
+    };
+    let known_user = user.is_some();
+
+    tokio::task::spawn_blocking(move || {
+        let Ok(hash) = PasswordHash::new(&hash) else {
+            return false;
+        };
+
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+            && known_user
+    })
+    .await
+    .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_password_verification() {
+        let user = User {
+            id: 1,
+            username: "aurora".into(),
+            password_hash: hash_password("hunter2").unwrap(),
+            email: None,
+            email_verified: false,
+            owner_id: None,
+            bot: false,
+        };
+
+        assert!(verify_password(Some(&user), "hunter2".into()).await);
+        assert!(!verify_password(Some(&user), "hunter3".into()).await);
+
+        // Unknown users never pass, not even with the dummy password.
+        assert!(!verify_password(None, "aurora-dummy-password".into()).await);
+    }
+}
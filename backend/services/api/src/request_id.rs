@@ -0,0 +1,102 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+use tower_http::request_id::RequestId;
+
+tokio::task_local! {
+    /// The ID of the request the current task is handling.
+    static REQUEST_ID: String;
+}
+
+/// The ID of the request currently being handled, if any.
+///
+/// Only available inside of [scope_request_id].
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// The ID assigned by [tower_http::request_id::SetRequestIdLayer], if any.
+pub fn from_request(request: &Request) -> Option<&str> {
+    request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+}
+
+/// Middleware making the request ID available through [current], use it with [axum::middleware::from_fn].
+///
+/// Must be layered inside of [tower_http::request_id::SetRequestIdLayer].
+pub async fn scope_request_id(request: Request, next: Next) -> Response {
+    let Some(id) = from_request(&request).map(str::to_owned) else {
+        return next.run(request).await;
+    };
+
+    REQUEST_ID.scope(id, next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::error::APIError;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+    use tracing_test::traced_test;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|| async { Err::<(), _>(APIError::ExpiredToken) }))
+            .layer(middleware::from_fn(scope_request_id))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+    }
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_request_id_generated() {
+        let response = app()
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let id = response.headers()["X-Request-Id"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(!id.is_empty());
+        assert_eq!(body_json(response).await["request_id"], id);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_request_id_propagated() {
+        let response = app()
+            .oneshot(
+                Request::get("/")
+                    .header("X-Request-Id", "support-ticket-1234")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["X-Request-Id"], "support-ticket-1234");
+        assert_eq!(
+            body_json(response).await["request_id"],
+            "support-ticket-1234"
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_request_id_outside_of_request() {
+        assert_eq!(current(), None);
+    }
+}
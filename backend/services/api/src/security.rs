@@ -0,0 +1,148 @@
+//! Browser facing security policy: CORS and security headers.
+//!
+//! # CORS
+//!
+//! Only origins listed in `CORS_ALLOWED_ORIGINS` (comma separated, e.g.
+//! `CORS_ALLOWED_ORIGINS=https://aurora.example,http://localhost:8080`) may call the API
+//! from a browser. If it is unset, no cross-origin requests are allowed; the web dev server
+//! proxies `/api` and is same-origin.
+//!
+//! The Electron client loads from `file://`, which browsers send as `Origin: null`.
+//! Every sandboxed iframe sends that as well, so only list `null` if you accept that.
+//!
+//! # CSRF
+//!
+//! The API authenticates with `Authorization: Bearer` tokens. Browsers never attach those
+//! on their own, so cross-site requests cannot act on behalf of a user and there is nothing
+//! to forge.
+//!
+//! The web app may use a session cookie instead, see [crate::v1::session]. That comes with
+//! a double-submit CSRF token and `SameSite=Strict`. Credentials (cookies) are not allowed in
+//! cross-origin requests, so cookie sessions only work when the app is served from the same
+//! origin as the API; the Electron client keeps using bearer tokens.
+//!
+//! # HSTS
+//!
+//! `Strict-Transport-Security` defaults to `max-age=63072000; includeSubDomains`. Set
+//! `STRICT_TRANSPORT_SECURITY` to replace it, e.g. with `max-age=63072000` while sibling
+//! subdomains still serve plain HTTP, or to `off` to leave it to a proxy in front.
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use tower_http::cors::CorsLayer;
+
+/// Set on every response, see [security_headers].
+const SECURITY_HEADERS: [(HeaderName, &str); 3] = [
+    (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    (header::X_FRAME_OPTIONS, "DENY"),
+    (header::REFERRER_POLICY, "no-referrer"),
+];
+
+/// `Strict-Transport-Security` unless configured otherwise, see the [module docs](self).
+const DEFAULT_HSTS: &str = "max-age=63072000; includeSubDomains";
+
+lazy_static! {
+    /// `Strict-Transport-Security` of every response, configured through
+    /// `STRICT_TRANSPORT_SECURITY`.
+    static ref HSTS: Option<HeaderValue> =
+        hsts(std::env::var("STRICT_TRANSPORT_SECURITY").ok().as_deref());
+}
+
+/// The `Strict-Transport-Security` header for the configured `value`, `None` if it is `off`.
+fn hsts(value: Option<&str>) -> Option<HeaderValue> {
+    match value.map(str::trim) {
+        None | Some("") => Some(HeaderValue::from_static(DEFAULT_HSTS)),
+        Some("off") => None,
+        Some(value) => Some(value.parse().unwrap_or_else(|_| {
+            panic!("STRICT_TRANSPORT_SECURITY is not a valid header value: '{value}'")
+        })),
+    }
+}
+
+/// Headers of our responses which browser clients may read.
+const EXPOSED_HEADERS: [&str; 6] = [
+    "x-request-id",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset-after",
+    "x-ratelimit-bucket",
+    "retry-after",
+];
+
+/// Read the allowed origins from `CORS_ALLOWED_ORIGINS`.
+pub fn allowed_origins_from_env() -> Vec<HeaderValue> {
+    let Ok(origins) = std::env::var("CORS_ALLOWED_ORIGINS") else {
+        return Vec::new();
+    };
+
+    origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| {
+            origin.parse().unwrap_or_else(|_| {
+                panic!("CORS_ALLOWED_ORIGINS contains an invalid origin: '{origin}'")
+            })
+        })
+        .collect()
+}
+
+/// CORS policy allowing `origins` to use the API.
+pub fn cors_layer(origins: Vec<HeaderValue>) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static("x-request-id"),
+            HeaderName::from_static("x-csrf-token"),
+        ])
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+        .max_age(std::time::Duration::from_secs(60 * 60))
+}
+
+/// Middleware setting standard security headers on every response, unless a handler
+/// set them already. Use it with [axum::middleware::from_fn].
+pub async fn security_headers(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    for (name, value) in SECURITY_HEADERS {
+        if !headers.contains_key(&name) {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+    }
+
+    // Browsers ignore this on plain HTTP, it only kicks in behind TLS.
+    if let Some(hsts) = &*HSTS {
+        if !headers.contains_key(header::STRICT_TRANSPORT_SECURITY) {
+            headers.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsts() {
+        assert_eq!(hsts(None).unwrap(), DEFAULT_HSTS);
+        assert_eq!(hsts(Some("")).unwrap(), DEFAULT_HSTS);
+        assert_eq!(hsts(Some("max-age=63072000")).unwrap(), "max-age=63072000");
+        assert_eq!(hsts(Some("off")), None);
+    }
+}
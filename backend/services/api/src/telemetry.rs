@@ -0,0 +1,143 @@
+use axum::extract::Request;
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::request_id;
+
+/// Keeps the OpenTelemetry exporter alive, call [Telemetry::shutdown] to flush it.
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Flush pending spans and stop exporting.
+    pub async fn shutdown(self) {
+        let Some(tracer_provider) = self.tracer_provider else {
+            return;
+        };
+
+        // Flushing blocks until the collector answered.
+        let result = tokio::task::spawn_blocking(move || tracer_provider.shutdown()).await;
+        if let Ok(Err(err)) = result {
+            warn!("Failed to flush OpenTelemetry spans: {err}");
+        }
+    }
+}
+
+/// Install the global tracing subscriber.
+///
+/// - Logs are filtered through `RUST_LOG` (e.g. `RUST_LOG=info,aurora_api=debug`).
+/// - `LOG_FORMAT=json` switches to one JSON object per line, for log shipping.
+/// - Setting `OTEL_EXPORTER_OTLP_ENDPOINT` (e.g. `http://localhost:4318`) additionally
+///   exports every span through OTLP/HTTP. The other `OTEL_*` variables are honored as well.
+pub fn init() -> anyhow::Result<Telemetry> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let fmt = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+        Ok("pretty") | Err(_) => tracing_subscriber::fmt::layer().boxed(),
+        Ok(format) => panic!("LOG_FORMAT must be either 'pretty' or 'json', got '{format}'"),
+    };
+
+    let tracer_provider = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(_) => Some(tracer_provider()?),
+        Err(_) => None,
+    };
+
+    let otel = tracer_provider.as_ref().map(|tracer_provider| {
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("aurora-api"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(otel)
+        .init();
+
+    if tracer_provider.is_some() {
+        info!("Exporting traces through OTLP");
+    }
+
+    Ok(Telemetry { tracer_provider })
+}
+
+fn tracer_provider() -> anyhow::Result<SdkTracerProvider> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    // The endpoint is picked up from OTEL_EXPORTER_OTLP_ENDPOINT.
+    let exporter = SpanExporter::builder().with_http().build()?;
+
+    let mut resource = Resource::builder();
+    if std::env::var("OTEL_SERVICE_NAME").is_err() {
+        resource = resource.with_service_name(env!("CARGO_PKG_NAME"));
+    }
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build())
+}
+
+/// Span for [tower_http::trace::TraceLayer], carrying the `X-Request-Id` of the request.
+///
+/// If the request carries a W3C `traceparent` header, the span continues that trace.
+pub fn make_span(request: &Request) -> Span {
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id = request_id::from_request(request).unwrap_or_default(),
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+
+    // Fails only if no OpenTelemetry layer is installed, nothing to propagate then.
+    let _ = span.set_parent(parent);
+
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_test::traced_test;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_traceparent_propagation() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let tracer_provider = SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("test")));
+
+        let request = Request::get("/")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(Body::empty())
+            .unwrap();
+
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            let span = make_span(&request);
+            span.context().span().span_context().trace_id()
+        });
+
+        assert_eq!(trace_id.to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+}
@@ -0,0 +1,169 @@
+//! Optional TLS termination, for deployments without a reverse proxy.
+//!
+//! Set both `TLS_CERT_PATH` (PEM, full chain) and `TLS_KEY_PATH` (PEM, PKCS#8, PKCS#1 or
+//! SEC1) to serve HTTPS. HTTP/2 is negotiated through ALPN, with HTTP/1.1 as fallback.
+//!
+//! The files are checked for changes every [RELOAD_INTERVAL], so renewed certificates
+//! (e.g. from certbot) are picked up without a restart. Polling instead of watching
+//! the files also catches the symlink swaps of cert-manager and friends.
+
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::time::Duration;
+
+/// How often the certificate and key are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where the certificate and key are read from.
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsPaths {
+    /// Read the paths from `TLS_CERT_PATH` and `TLS_KEY_PATH`, if TLS is configured.
+    pub fn from_env() -> Option<Self> {
+        match (
+            std::env::var_os("TLS_CERT_PATH"),
+            std::env::var_os("TLS_KEY_PATH"),
+        ) {
+            (Some(cert), Some(key)) => Some(Self {
+                cert: cert.into(),
+                key: key.into(),
+            }),
+            (None, None) => None,
+            _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+        }
+    }
+
+    /// Latest modification time of the certificate and key.
+    async fn modified(&self) -> Option<SystemTime> {
+        modified(&self.cert).await.max(modified(&self.key).await)
+    }
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    metadata.modified().ok()
+}
+
+/// Load the certificate and key.
+pub async fn config(paths: &TlsPaths) -> anyhow::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&paths.cert, &paths.key)
+        .await
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to load TLS certificate '{}' or key '{}': {err}",
+                paths.cert.display(),
+                paths.key.display()
+            )
+        })
+}
+
+/// Reload `config` whenever the certificate or key change, forever.
+///
+/// A broken certificate is logged and the previous one is kept in use.
+pub async fn watch(config: RustlsConfig, paths: TlsPaths) {
+    let mut last_modified = paths.modified().await;
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let modified = paths.modified().await;
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        reload(&config, &paths).await;
+    }
+}
+
+async fn reload(config: &RustlsConfig, paths: &TlsPaths) -> bool {
+    match config.reload_from_pem_file(&paths.cert, &paths.key).await {
+        Ok(()) => {
+            info!(cert = %paths.cert.display(), "Reloaded TLS certificate");
+            true
+        }
+        Err(err) => {
+            warn!(
+                cert = %paths.cert.display(),
+                "Failed to reload TLS certificate, keeping the current one: {err}"
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tracing_test::traced_test;
+
+    /// Writes a fresh self-signed certificate for `localhost` to a temporary directory.
+    fn write_certificate(name: &str) -> TlsPaths {
+        let dir = std::env::temp_dir().join(format!("aurora-tls-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let paths = TlsPaths {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+
+        let certificate = rcgen::generate_simple_self_signed(["localhost".to_owned()]).unwrap();
+        std::fs::write(&paths.cert, certificate.cert.pem()).unwrap();
+        std::fs::write(&paths.key, certificate.key_pair.serialize_pem()).unwrap();
+
+        paths
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_tls_config_alpn() {
+        let paths = write_certificate("alpn");
+        let config = config(&paths).await.unwrap();
+
+        assert_eq!(
+            config.get_inner().alpn_protocols,
+            [b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_tls_reload() {
+        let paths = write_certificate("reload");
+        let config = config(&paths).await.unwrap();
+        let before = config.get_inner();
+
+        // Renewed certificates are swapped in.
+        write_certificate("reload");
+        assert!(reload(&config, &paths).await);
+        assert!(!Arc::ptr_eq(&before, &config.get_inner()));
+
+        // Broken ones are not.
+        let current = config.get_inner();
+        std::fs::write(&paths.cert, "not a certificate").unwrap();
+        assert!(!reload(&config, &paths).await);
+        assert!(Arc::ptr_eq(&current, &config.get_inner()));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_tls_missing_files() {
+        let paths = TlsPaths {
+            cert: "/nonexistent/cert.pem".into(),
+            key: "/nonexistent/key.pem".into(),
+        };
+
+        assert!(config(&paths).await.is_err());
+        assert_eq!(paths.modified().await, None);
+    }
+}
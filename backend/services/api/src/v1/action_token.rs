@@ -0,0 +1,237 @@
+use base64::prelude::*;
+use hmac::Mac;
+
+use super::token::{keyed_hmac, TokenError};
+
+const BASE64: base64::engine::GeneralPurpose = BASE64_URL_SAFE_NO_PAD;
+
+type Result<T> = std::result::Result<T, TokenError>;
+
+/// What an [ActionToken] allows to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::VerifyEmail => "verify-email",
+            Self::ResetPassword => "reset-password",
+        }
+    }
+
+    /// How long a token stays valid, in seconds.
+    fn lifetime(self) -> i64 {
+        match self {
+            Self::VerifyEmail => 24 * 60 * 60,
+            Self::ResetPassword => 60 * 60,
+        }
+    }
+}
+
+/// Single-use token sent by email, e.g. in a password reset link.
+///
+/// It is composed from an action, a user ID, an expiry time and a HMAC.
+/// The HMAC also covers a `binding`: the state the action consumes, e.g. the current
+/// password hash for [Action::ResetPassword]. Once the action ran, the binding changed,
+/// so the token can't be used again and nothing has to be stored.
+///
+/// The token is URL safe and encoded as follows:
+/// ```text
+/// // <hmac> := Base64Url(HMAC<SHA512>(<action>.<user_id>.<expires_at>.<binding>))
+///
+/// <action>.<user_id>.<expires_at>.<hmac>
+/// ```
+#[derive(Debug, Clone)]
+pub struct ActionToken {
+    pub action: Action,
+
+    /// The user ID of the user this token belongs to.
+    pub user_id: u64,
+
+    /// When this token expires, in seconds since the unix epoch.
+    pub expires_at: i64,
+
+    hmac: Vec<u8>,
+}
+
+impl ActionToken {
+    /// Create a token allowing `action` for the user `user_id`, as long as `binding` doesn't change.
+    pub fn new(action: Action, user_id: u64, binding: &str) -> Result<Self> {
+        let expires_at = time::OffsetDateTime::now_utc().unix_timestamp() + action.lifetime();
+
+        let mut token = Self {
+            action,
+            user_id,
+            expires_at,
+            hmac: Vec::new(),
+        };
+        token.hmac = token.hmac(binding)?.finalize().into_bytes().to_vec();
+
+        Ok(token)
+    }
+
+    /// Read a token for `action`, the HMAC still has to be checked with [ActionToken::verify].
+    ///
+    /// # Errors
+    /// - [TokenError::InvalidFormat] The token is malformed or for another action.
+    /// - [TokenError::HmacDecoding] Failed to decode the HMAC from Base64.
+    /// - [TokenError::Expired] The token expired.
+    pub fn parse(token: &str, action: Action) -> Result<Self> {
+        let mut components = token.split('.');
+
+        let (Some(token_action), Some(user_id), Some(expires_at), Some(hmac), None) = (
+            components.next(),
+            components.next(),
+            components.next(),
+            components.next(),
+            components.next(),
+        ) else {
+            return Err(TokenError::InvalidFormat);
+        };
+
+        if token_action != action.as_str() {
+            return Err(TokenError::InvalidFormat);
+        }
+
+        let token = Self {
+            action,
+            user_id: user_id.parse().map_err(|_| TokenError::InvalidFormat)?,
+            expires_at: expires_at.parse().map_err(|_| TokenError::InvalidFormat)?,
+            hmac: BASE64.decode(hmac).map_err(|_| TokenError::HmacDecoding)?,
+        };
+
+        if token.expires_at < time::OffsetDateTime::now_utc().unix_timestamp() {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(token)
+    }
+
+    /// Verify the token against the current `binding`.
+    ///
+    /// # Errors
+    /// - [TokenError::HmacGeneration] Failed to create HMAC for validation.
+    /// - [TokenError::HmacVerification] The token is forged, or the binding changed.
+    pub fn verify(&self, binding: &str) -> Result<()> {
+        self.hmac(binding)?
+            .verify_slice(&self.hmac)
+            .map_err(|_| TokenError::HmacVerification)
+    }
+
+    fn hmac(&self, binding: &str) -> Result<hmac::Hmac<sha2::Sha512>> {
+        let mut hmac = keyed_hmac()?;
+
+        hmac.update(
+            format!(
+                "{action}.{user_id}.{expires_at}.{binding}",
+                action = self.action.as_str(),
+                user_id = self.user_id,
+                expires_at = self.expires_at,
+            )
+            .as_bytes(),
+        );
+
+        Ok(hmac)
+    }
+}
+
+impl From<ActionToken> for String {
+    fn from(token: ActionToken) -> Self {
+        format!(
+            "{action}.{user_id}.{expires_at}.{hmac}",
+            action = token.action.as_str(),
+            user_id = token.user_id,
+            expires_at = token.expires_at,
+            hmac = BASE64.encode(token.hmac),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    fn setup() {
+        std::env::set_var("HMAC_SECURITY_KEY", "TODO: secret key");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_action_token_roundtrip() {
+        setup();
+
+        let token: String = ActionToken::new(Action::ResetPassword, 1, "hash")
+            .unwrap()
+            .into();
+
+        let token = ActionToken::parse(&token, Action::ResetPassword).unwrap();
+        assert_eq!(token.user_id, 1);
+        assert!(token.verify("hash").is_ok());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_action_token_single_use() {
+        setup();
+
+        let token = ActionToken::new(Action::ResetPassword, 1, "old hash").unwrap();
+
+        // The password changed since, so the link is spent.
+        assert!(token
+            .verify("new hash")
+            .is_err_and(|e| e == TokenError::HmacVerification));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_action_token_wrong_action() {
+        setup();
+
+        let token: String = ActionToken::new(Action::VerifyEmail, 1, "aurora@example.com")
+            .unwrap()
+            .into();
+
+        assert!(ActionToken::parse(&token, Action::ResetPassword)
+            .is_err_and(|e| e == TokenError::InvalidFormat));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_action_token_expired() {
+        setup();
+
+        let mut token = ActionToken::new(Action::ResetPassword, 1, "hash").unwrap();
+        token.expires_at -= Action::ResetPassword.lifetime() + 1;
+
+        assert!(
+            ActionToken::parse(&String::from(token), Action::ResetPassword)
+                .is_err_and(|e| e == TokenError::Expired)
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_action_token_tampered() {
+        setup();
+
+        let token: String = ActionToken::new(Action::ResetPassword, 1, "hash")
+            .unwrap()
+            .into();
+        let tampered = token.replacen(".1.", ".2.", 1);
+
+        let token = ActionToken::parse(&tampered, Action::ResetPassword).unwrap();
+        assert!(token
+            .verify("hash")
+            .is_err_and(|e| e == TokenError::HmacVerification));
+
+        assert!(
+            ActionToken::parse("reset-password.1.2", Action::ResetPassword)
+                .is_err_and(|e| e == TokenError::InvalidFormat)
+        );
+    }
+}
@@ -4,7 +4,10 @@ use axum::{
     Json,
 };
 
-use super::token::TokenError;
+use super::{
+    token::TokenError,
+    validation::{FieldError, FieldErrors},
+};
 
 /// Generalized error type for the API.
 ///
@@ -15,82 +18,366 @@ use super::token::TokenError;
 /// 20000 - Bot-related errors
 /// 30000 - Limits reached
 /// 40000 - Authorization errors
-/// 50000 - Access errors
-#[repr(u64)]
+/// 50000 - Access and request errors, e.g. missing scopes or an invalid body
+///
+/// The code of every variant is assigned in [APIError::code], and pinned by tests.
+/// Codes must never change once released, clients depend on them.
 #[derive(thiserror::Error, Clone, Debug)]
 pub enum APIError {
     #[error("{0} - {1}")]
-    GenericError(StatusCode, String) = 0,
+    GenericError(StatusCode, String),
     /// A user was requested, but we don't know them.
     /// Perhaps they were deleted? or perhaps they never existed?
     /// we don't know.
     #[error("The user requested is not known to us: '{who:?}'.")]
-    UnknownUser { who: Option<String> } = 10001,
+    UnknownUser { who: Option<String> },
+
+    /// The application requested does not exist, or belongs to someone else.
+    #[error("Unknown application.")]
+    UnknownApplication,
+
+    /// The endpoint is for users only, e.g. refreshing a login.
+    #[error("Bots cannot use this endpoint.")]
+    BotsForbidden,
+
+    /// Too many requests were sent in a short amount of time.
+    #[error("You are being rate limited. Retry after {retry_after} seconds.")]
+    RateLimited { retry_after: u64 },
 
     /// A header was missing from the request.
     #[error("Lack of {header} header")]
-    MissingHeader { header: &'static str } = 40001,
+    MissingHeader { header: &'static str },
 
     /// A header was present, but it was not in the correct format.
     #[error("Invalid {header} header format. Must be: '{format}'.")]
     InvalidHeader {
         header: &'static str,
         format: &'static str,
-    } = 40002,
+    },
 
     /// The token provided was invalid.
     #[error("Invalid token provided.")]
-    InvalidToken(#[from] TokenError) = 40003,
+    InvalidToken(#[from] TokenError),
 
     /// The token provided was valid, but it was expired.
     #[error("Expired token provided.")]
-    ExpiredToken = 40004,
+    ExpiredToken,
+
+    /// The login or password did not match any account.
+    #[error("Invalid login or password.")]
+    InvalidCredentials,
+
+    /// Too many failed logins for this account or from this address.
+    #[error("Too many failed login attempts. Retry after {retry_after} seconds.")]
+    LoginCooldown { retry_after: u64 },
+
+    /// A cookie authenticated request lacked the CSRF token of its session.
+    #[error("Missing or invalid CSRF token.")]
+    InvalidCsrfToken,
+
+    /// The token of an email link was invalid, expired or used already.
+    #[error("Invalid or expired link.")]
+    InvalidActionToken,
+
+    /// An OAuth2 access token was used on an endpoint outside of its scopes.
+    #[error("Missing access.")]
+    MissingAccess,
+
+    /// The request body was malformed or failed validation.
+    /// `errors` tells which fields are at fault, and why.
+    #[error("Invalid form body.")]
+    InvalidFormBody { errors: FieldErrors },
 }
 
 impl APIError {
-    fn discriminant(&self) -> u64 {
-        // SAFETY: because #[repr(u64)] is used, the discriminant is the first 8 bytes of the enum
-        unsafe { *(self as *const Self as *const u64) }
+    /// The numeric code of this error, as sent to clients.
+    pub fn code(&self) -> u64 {
+        match self {
+            // 0 - Generic error
+            Self::GenericError(..) => 0,
+
+            // 10000 - Unknown entities
+            Self::UnknownUser { .. } => 10001,
+            Self::UnknownApplication => 10002,
+
+            // 20000 - Bot-related errors
+            Self::BotsForbidden => 20001,
+
+            // 30000 - Limits reached
+            Self::RateLimited { .. } => 30001,
+
+            // 40000 - Authorization errors
+            Self::MissingHeader { .. } => 40001,
+            Self::InvalidHeader { .. } => 40002,
+            Self::InvalidToken(_) => 40003,
+            Self::ExpiredToken => 40004,
+            Self::InvalidCredentials => 40005,
+            Self::LoginCooldown { .. } => 40006,
+            Self::InvalidCsrfToken => 40007,
+            Self::InvalidActionToken => 40008,
+
+            // 50000 - Access and request errors
+            Self::MissingAccess => 50001,
+            Self::InvalidFormBody { .. } => 50035,
+        }
+    }
+
+    /// The HTTP status this error is answered with.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            // 0 - Generic error
+            Self::GenericError(status_code, _) => *status_code,
+
+            // 10000 - Unknown entities
+            Self::UnknownUser { .. } => StatusCode::NOT_FOUND,
+            Self::UnknownApplication => StatusCode::NOT_FOUND,
+
+            // 20000 - Bot-related errors
+            Self::BotsForbidden => StatusCode::FORBIDDEN,
+
+            // 30000 - Limits reached
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+
+            // 40000 - Authorization errors
+            Self::MissingHeader { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidHeader { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::UNAUTHORIZED,
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::LoginCooldown { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::InvalidCsrfToken => StatusCode::FORBIDDEN,
+            Self::InvalidActionToken => StatusCode::BAD_REQUEST,
+
+            // 50000 - Access and request errors
+            Self::MissingAccess => StatusCode::FORBIDDEN,
+            Self::InvalidFormBody { .. } => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// What this error means, independent of the details of a single occurrence.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::GenericError(..) => "Something went wrong, see the message for details.",
+            Self::UnknownUser { .. } => "The requested user does not exist.",
+            Self::UnknownApplication => "The requested application does not exist, or isn't yours.",
+            Self::BotsForbidden => "The endpoint is for users only, bots cannot use it.",
+            Self::RateLimited { .. } => {
+                "Too many requests were sent. Wait for the time in the `Retry-After` header."
+            }
+            Self::MissingHeader { .. } => "A required header is missing.",
+            Self::InvalidHeader { .. } => "A header is not in the required format.",
+            Self::InvalidToken(_) => "The token could not be decoded or failed verification.",
+            Self::ExpiredToken => "The token expired, log in again.",
+            Self::InvalidCredentials => "The login or password is wrong.",
+            Self::LoginCooldown { .. } => {
+                "Too many failed logins. Wait for the time in the `Retry-After` header."
+            }
+            Self::InvalidCsrfToken => {
+                "Requests authenticated by the session cookie must repeat the `aurora_csrf` cookie in the `X-CSRF-Token` header."
+            }
+            Self::InvalidActionToken => {
+                "The token from an email link is invalid, expired or was used already."
+            }
+            Self::MissingAccess => {
+                "OAuth2 access tokens only work on the endpoints their scopes allow."
+            }
+            Self::InvalidFormBody { .. } => {
+                "The request body is malformed or invalid, `errors` lists the problems per field."
+            }
+        }
+    }
+
+    /// One of every error, used to document them. See [super::openapi].
+    pub fn examples() -> Vec<Self> {
+        vec![
+            Self::GenericError(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error.".into(),
+            ),
+            Self::UnknownUser { who: None },
+            Self::UnknownApplication,
+            Self::BotsForbidden,
+            Self::RateLimited { retry_after: 60 },
+            Self::MissingHeader {
+                header: "Authorization",
+            },
+            Self::InvalidHeader {
+                header: "Authorization",
+                format: "Bearer <token>",
+            },
+            Self::InvalidToken(TokenError::InvalidToken),
+            Self::ExpiredToken,
+            Self::InvalidCredentials,
+            Self::LoginCooldown { retry_after: 60 },
+            Self::InvalidCsrfToken,
+            Self::InvalidActionToken,
+            Self::MissingAccess,
+            Self::InvalidFormBody {
+                errors: FieldErrors::from([(
+                    "login".into(),
+                    vec![FieldError::new("required", "This field is required.")],
+                )]),
+            },
+        ]
+    }
+}
+
+impl From<sqlx::Error> for APIError {
+    fn from(err: sqlx::Error) -> Self {
+        error!("Database error: {err}");
+
+        Self::GenericError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal server error.".into(),
+        )
     }
 }
 
 pub type APIResult<T> = Result<T, APIError>;
 
-#[derive(serde::Serialize)]
-struct JSONError {
+/// The body of every error response.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct JSONError {
+    /// The code of the error, see [APIError].
     code: u64,
+
+    /// Human readable description of the error.
     message: String,
+
+    /// The `X-Request-Id` of the failed request, to be quoted in support tickets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+
+    /// Problems per field of the request body, only set for `50035`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<FieldErrors>,
 }
 
-/// Simple macro to reduce code duplication.
-macro_rules! impl_err {
-    ($error:expr, $status_code:expr) => {
-        (
-            $status_code,
-            JSONError {
-                code: $error.discriminant(),
-                message: $error.to_string(),
+impl From<&APIError> for JSONError {
+    fn from(err: &APIError) -> Self {
+        Self {
+            code: err.code(),
+            message: err.to_string(),
+            request_id: crate::request_id::current(),
+            errors: match err {
+                APIError::InvalidFormBody { errors } => Some(errors.clone()),
+                _ => None,
             },
-        )
-    };
+        }
+    }
 }
 
 impl IntoResponse for APIError {
     fn into_response(self) -> Response {
-        let (status_code, obj) = match &self {
-            // 0 - Generic error
-            Self::GenericError(status_code, _) => impl_err!(self, *status_code),
+        let mut response = (self.status_code(), Json(JSONError::from(&self))).into_response();
 
-            // 10000 - Unknown entities
-            Self::UnknownUser { .. } => impl_err!(self, StatusCode::NOT_FOUND),
+        if let Self::RateLimited { retry_after } | Self::LoginCooldown { retry_after } = self {
+            response
+                .headers_mut()
+                .insert("Retry-After", retry_after.into());
+        }
 
-            // 40000 - Authorization errors
-            Self::MissingHeader { .. } => impl_err!(self, StatusCode::BAD_REQUEST),
-            Self::InvalidHeader { .. } => impl_err!(self, StatusCode::BAD_REQUEST),
-            Self::InvalidToken(_) => impl_err!(self, StatusCode::UNAUTHORIZED),
-            Self::ExpiredToken => impl_err!(self, StatusCode::UNAUTHORIZED),
-        };
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Amount of variants of [APIError], bump it together with [variant_index].
+    const VARIANTS: usize = 15;
+
+    /// Fails to compile when a variant is added, as a reminder to add it to [APIError::examples].
+    fn variant_index(err: &APIError) -> usize {
+        match err {
+            APIError::GenericError(..) => 0,
+            APIError::UnknownUser { .. } => 1,
+            APIError::UnknownApplication => 2,
+            APIError::BotsForbidden => 3,
+            APIError::RateLimited { .. } => 4,
+            APIError::MissingHeader { .. } => 5,
+            APIError::InvalidHeader { .. } => 6,
+            APIError::InvalidToken(_) => 7,
+            APIError::ExpiredToken => 8,
+            APIError::InvalidCredentials => 9,
+            APIError::LoginCooldown { .. } => 10,
+            APIError::InvalidCsrfToken => 11,
+            APIError::InvalidActionToken => 12,
+            APIError::MissingAccess => 13,
+            APIError::InvalidFormBody { .. } => 14,
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        // Changing any of these breaks clients, add new codes instead.
+        let expected: [(u64, u16); VARIANTS] = [
+            (0, 500),
+            (10001, 404),
+            (10002, 404),
+            (20001, 403),
+            (30001, 429),
+            (40001, 400),
+            (40002, 400),
+            (40003, 401),
+            (40004, 401),
+            (40005, 401),
+            (40006, 429),
+            (40007, 403),
+            (40008, 400),
+            (50001, 403),
+            (50035, 400),
+        ];
+
+        let actual: Vec<(u64, u16)> = APIError::examples()
+            .iter()
+            .map(|err| (err.code(), err.status_code().as_u16()))
+            .collect();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_error_codes_unique() {
+        let mut codes: Vec<u64> = APIError::examples().iter().map(APIError::code).collect();
+        codes.sort();
+        codes.dedup();
+
+        assert_eq!(codes.len(), VARIANTS);
+    }
+
+    #[test]
+    fn test_generic_error_status() {
+        let err = APIError::GenericError(StatusCode::IM_A_TEAPOT, "Short and stout.".into());
+
+        assert_eq!(err.code(), 0);
+        assert_eq!(err.status_code(), StatusCode::IM_A_TEAPOT);
+    }
+
+    #[tokio::test]
+    async fn test_error_response() {
+        let response = APIError::LoginCooldown { retry_after: 42 }.into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["Retry-After"], "42");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["code"], 40006);
+        assert_eq!(
+            body["message"],
+            "Too many failed login attempts. Retry after 42 seconds."
+        );
+    }
+
+    #[test]
+    fn test_examples_exhaustive() {
+        let indices: Vec<usize> = APIError::examples().iter().map(variant_index).collect();
 
-        (status_code, Json(obj)).into_response()
+        assert_eq!(indices, (0..VARIANTS).collect::<Vec<_>>());
     }
 }
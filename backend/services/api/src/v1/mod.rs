@@ -1,15 +1,119 @@
+use std::sync::Arc;
+
 use axum::routing::{get, post};
-use axum::Router;
+use axum::{middleware, Router};
+use sqlx::PgPool;
+use tokio::time::Duration;
+use utoipa::OpenApi;
+use utoipa_redoc::{Redoc, Servable};
+
+use self::ratelimit::RateLimiter;
 
+pub mod action_token;
 pub mod error;
+pub mod oauth2;
+pub mod openapi;
+pub mod ratelimit;
 pub mod routes;
+pub mod session;
 pub mod token;
+pub mod validation;
 
-pub fn register_routes<S>() -> Router<S>
-where
-    S: std::marker::Sync + std::marker::Send + std::clone::Clone + 'static,
-{
-    Router::new()
-        .route("/auth/login", get(routes::auth::get_login))
+pub fn register_routes(pool: PgPool) -> Router<PgPool> {
+    //
+    // Rate limit buckets, see [RateLimiter::from_env] for how to configure them.
+    // Routes anyone may call are limited per IP, see [ratelimit::RateLimitKey].
+    //
+    // Routes taking credentials, or sending mail.
+    let auth_bucket = Arc::new(RateLimiter::from_env("auth", 5, Duration::from_secs(60)));
+
+    let auth = Router::new()
         .route("/auth/login", post(routes::auth::post_login))
+        .route("/auth/register", post(routes::auth::post_register))
+        .route(
+            "/auth/verify-email/resend",
+            post(routes::auth::post_resend_verification),
+        )
+        .route(
+            "/auth/password-reset",
+            post(routes::auth::post_password_reset),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            auth_bucket,
+            ratelimit::rate_limit,
+        ));
+
+    // Routes taking a session or action token, which can't be guessed.
+    let session_bucket = Arc::new(RateLimiter::from_env(
+        "session",
+        30,
+        Duration::from_secs(60),
+    ));
+
+    let session = Router::new()
+        .route("/auth/login", get(routes::auth::get_login))
+        .route("/auth/logout", post(routes::auth::post_logout))
+        .route("/auth/verify-email", post(routes::auth::post_verify_email))
+        .route(
+            "/auth/password-reset/confirm",
+            post(routes::auth::post_password_reset_confirm),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            session_bucket,
+            ratelimit::rate_limit,
+        ));
+
+    let applications_bucket = Arc::new(RateLimiter::from_env(
+        "applications",
+        10,
+        Duration::from_secs(60),
+    ));
+
+    let applications = Router::new()
+        .route("/applications", get(routes::applications::get_applications))
+        .route(
+            "/applications",
+            post(routes::applications::post_application),
+        )
+        .route(
+            "/applications/:id/bot/reset",
+            post(routes::applications::post_reset_bot_token),
+        )
+        .route(
+            "/applications/:id/oauth2",
+            get(routes::applications::get_oauth2_client)
+                .put(routes::applications::put_oauth2_client),
+        )
+        .route(
+            "/applications/:id/oauth2/secret/reset",
+            post(routes::applications::post_reset_client_secret),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            (applications_bucket, pool),
+            ratelimit::rate_limit_users,
+        ));
+
+    let oauth2_bucket = Arc::new(RateLimiter::from_env("oauth2", 20, Duration::from_secs(60)));
+
+    let oauth2 = Router::new()
+        .route(
+            "/oauth2/authorize",
+            get(routes::oauth2::get_authorize).post(routes::oauth2::post_authorize),
+        )
+        .route("/oauth2/token", post(routes::oauth2::post_token))
+        .route("/oauth2/token/revoke", post(routes::oauth2::post_revoke))
+        .route_layer(middleware::from_fn_with_state(
+            oauth2_bucket,
+            ratelimit::rate_limit,
+        ));
+
+    Router::new() //
+        .merge(auth)
+        .merge(session)
+        .merge(applications)
+        .merge(oauth2)
+        .route("/users/@me", get(routes::users::get_me))
+        .route("/errors", get(routes::errors::get_errors))
+        .route("/openapi.json", get(openapi::get_openapi))
+        .merge(Redoc::with_url("/docs", openapi::ApiDoc::openapi()))
 }
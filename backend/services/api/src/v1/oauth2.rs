@@ -0,0 +1,356 @@
+//! OAuth2 authorization server, for third-party apps acting on behalf of users.
+//!
+//! Applications register as clients (see [crate::models::oauth2::Client]) and use the
+//! authorization code flow with PKCE (`S256` only), RFC 6749 and RFC 7636:
+//! 1. The web app shows the consent screen for `GET /oauth2/authorize`, and sends the
+//!    decision to `POST /oauth2/authorize`, which tells where to redirect the browser.
+//! 2. The application exchanges the code at `POST /oauth2/token`, authenticating with its
+//!    secret if it is confidential, and gets an access token plus a refresh token.
+//! 3. `POST /oauth2/token/revoke` (RFC 7009) ends the authorization.
+//!
+//! Access tokens are [AuthenticationToken]s carrying a [Grant], so they expire like any
+//! other token. Endpoints accept them through [super::session::Scoped] only.
+//!
+//! [AuthenticationToken]: super::token::AuthenticationToken
+
+use std::fmt;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::prelude::*;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use url::Url;
+
+use super::token::TokenError;
+
+const BASE64: base64::engine::GeneralPurpose = BASE64_URL_SAFE_NO_PAD;
+
+/// What an access token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Read the ID and username of the user, `GET /users/@me`.
+    Identify,
+
+    /// Also read their email, on `GET /users/@me`.
+    Email,
+}
+
+impl Scope {
+    pub const ALL: [Self; 2] = [Self::Identify, Self::Email];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Identify => "identify",
+            Self::Email => "email",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Identify => "Read your ID and username.",
+            Self::Email => "Read your email address.",
+        }
+    }
+}
+
+/// A set of [Scope]s, space separated on the wire like `identify email`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scopes(Vec<Scope>);
+
+impl Scopes {
+    /// Parse space separated scopes.
+    ///
+    /// # Errors
+    /// The first unknown scope.
+    pub fn parse(scopes: &str) -> Result<Self, String> {
+        let mut parsed = scopes
+            .split(' ')
+            .filter(|scope| !scope.is_empty())
+            .map(|scope| {
+                Scope::ALL
+                    .into_iter()
+                    .find(|known| known.as_str() == scope)
+                    .ok_or_else(|| scope.to_owned())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        parsed.sort();
+        parsed.dedup();
+
+        Ok(Self(parsed))
+    }
+
+    pub fn contains(&self, scope: Scope) -> bool {
+        self.0.contains(&scope)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether every scope of `self` is in `other` as well.
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.0.iter().all(|scope| other.contains(*scope))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Scope> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl fmt::Display for Scopes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, scope) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(scope.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Marks the [Scope] an endpoint requires from access tokens, see [super::session::Scoped].
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+/// [Scope::Identify]
+#[derive(Debug, Clone, Copy)]
+pub struct Identify;
+
+impl RequiredScope for Identify {
+    const SCOPE: Scope = Scope::Identify;
+}
+
+/// A random secret, e.g. an authorization code or a client secret.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    BASE64.encode(bytes)
+}
+
+/// Hash of a secret from [generate_secret], for storage.
+///
+/// The secrets are random, so a plain SHA-256 is enough to keep a database leak useless.
+pub fn hash_secret(secret: &str) -> String {
+    BASE64.encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Check a secret against its [hash_secret], in constant time.
+pub fn verify_secret(secret: &str, hash: &str) -> bool {
+    hash_secret(secret).as_bytes().ct_eq(hash.as_bytes()).into()
+}
+
+/// Check a PKCE `code_verifier` against the `S256` `code_challenge` of the code.
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    let well_formed = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte));
+
+    well_formed
+        && BASE64
+            .encode(Sha256::digest(code_verifier.as_bytes()))
+            .as_bytes()
+            .ct_eq(code_challenge.as_bytes())
+            .into()
+}
+
+/// Check a redirect URI an application registers.
+///
+/// It has to be absolute and without fragment. Plain HTTP is only allowed for loopback
+/// addresses, which native apps listen on.
+pub fn validate_redirect_uri(redirect_uri: &str) -> Result<(), &'static str> {
+    let url = Url::parse(redirect_uri).map_err(|_| "Not a valid URL.")?;
+
+    if url.fragment().is_some() {
+        return Err("Must not have a fragment.");
+    }
+
+    match (url.scheme(), url.host_str()) {
+        ("https", Some(_)) => Ok(()),
+        ("http", Some("localhost" | "127.0.0.1" | "[::1]")) => Ok(()),
+        _ => Err("Must use https, or http on a loopback address."),
+    }
+}
+
+/// `redirect_uri` with `params` added to its query, where the browser is sent after consent.
+pub fn redirect_location(redirect_uri: &str, params: &[(&str, &str)]) -> String {
+    // Registered URIs were validated, see [validate_redirect_uri].
+    let Ok(mut url) = Url::parse(redirect_uri) else {
+        return redirect_uri.to_owned();
+    };
+
+    url.query_pairs_mut().extend_pairs(params);
+    url.into()
+}
+
+/// Errors of the token and revocation endpoints, answered as RFC 6749 section 5.2
+/// prescribes instead of as [super::error::APIError], for OAuth2 libraries to understand.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum OAuth2Error {
+    #[error("{0}")]
+    InvalidRequest(&'static str),
+
+    #[error("Client authentication failed.")]
+    InvalidClient,
+
+    #[error("{0}")]
+    InvalidGrant(&'static str),
+
+    #[error("Only `authorization_code` and `refresh_token` are supported.")]
+    UnsupportedGrantType,
+
+    #[error("The requested scope exceeds the authorized one.")]
+    InvalidScope,
+
+    #[error("Internal server error.")]
+    ServerError,
+}
+
+impl OAuth2Error {
+    fn error(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant(_) => "invalid_grant",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::InvalidScope => "invalid_scope",
+            Self::ServerError => "server_error",
+        }
+    }
+}
+
+/// The body of every [OAuth2Error] response.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct OAuth2ErrorResponse {
+    /// The RFC 6749 error code, e.g. `invalid_grant`.
+    error: &'static str,
+    error_description: String,
+}
+
+impl IntoResponse for OAuth2Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::InvalidClient => StatusCode::UNAUTHORIZED,
+            Self::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+
+        let body = OAuth2ErrorResponse {
+            error: self.error(),
+            error_description: self.to_string(),
+        };
+
+        let mut response = (status, no_store(), Json(body)).into_response();
+        if self == Self::InvalidClient {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"oauth2\""),
+            );
+        }
+
+        response
+    }
+}
+
+impl From<sqlx::Error> for OAuth2Error {
+    fn from(err: sqlx::Error) -> Self {
+        error!("Database error: {err}");
+        Self::ServerError
+    }
+}
+
+impl From<TokenError> for OAuth2Error {
+    fn from(err: TokenError) -> Self {
+        error!("Failed to issue token: {err}");
+        Self::ServerError
+    }
+}
+
+/// Responses carrying tokens must not be cached, RFC 6749 section 5.1.
+pub fn no_store() -> [(header::HeaderName, HeaderValue); 2] {
+    [
+        (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+        (header::PRAGMA, HeaderValue::from_static("no-cache")),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_scopes() {
+        let scopes = Scopes::parse("email  identify email").unwrap();
+        assert_eq!(scopes.to_string(), "identify email");
+        assert!(scopes.contains(Scope::Email));
+
+        let identify = Scopes::parse("identify").unwrap();
+        assert!(identify.is_subset(&scopes));
+        assert!(!scopes.is_subset(&identify));
+
+        assert_eq!(Scopes::parse("identify guilds"), Err("guilds".to_owned()));
+        assert!(Scopes::parse("").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_pkce() {
+        // The example of RFC 7636, appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+        assert!(verify_pkce(verifier, challenge));
+        assert!(!verify_pkce(&verifier.replace('d', "e"), challenge));
+        assert!(!verify_pkce("too-short", challenge));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_redirect_uri_validation() {
+        assert!(validate_redirect_uri("https://app.example/callback").is_ok());
+        assert!(validate_redirect_uri("http://127.0.0.1:8000/callback").is_ok());
+
+        assert!(validate_redirect_uri("http://app.example/callback").is_err());
+        assert!(validate_redirect_uri("https://app.example/#callback").is_err());
+        assert!(validate_redirect_uri("javascript:alert(1)").is_err());
+        assert!(validate_redirect_uri("/callback").is_err());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_redirect_location() {
+        assert_eq!(
+            redirect_location(
+                "https://app.example/callback?tab=1",
+                &[("code", "abc"), ("state", "a b&c")]
+            ),
+            "https://app.example/callback?tab=1&code=abc&state=a+b%26c"
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_secrets() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 43);
+        assert_ne!(secret, generate_secret());
+        assert_eq!(hash_secret(&secret), hash_secret(&secret));
+        assert_ne!(hash_secret(&secret), secret);
+
+        assert!(verify_secret(&secret, &hash_secret(&secret)));
+        assert!(!verify_secret(&generate_secret(), &hash_secret(&secret)));
+        assert!(!verify_secret(&secret, ""));
+    }
+}
@@ -0,0 +1,213 @@
+use axum::Json;
+use utoipa::{
+    openapi::{
+        security::{
+            ApiKey, ApiKeyValue, AuthorizationCode, Flow, HttpAuthScheme, HttpBuilder, OAuth2,
+            Scopes, SecurityScheme,
+        },
+        RefOr, Schema,
+    },
+    Modify, OpenApi,
+};
+
+use super::{
+    error::{APIError, JSONError},
+    oauth2::{OAuth2ErrorResponse, Scope},
+    routes, session,
+    validation::FieldError,
+};
+
+/// OpenAPI document of the v1 API, generated from the route handlers.
+///
+/// A copy is checked in as `openapi.json`, regenerate it with `UPDATE_OPENAPI=1 cargo test`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Aurora API", description = "REST API of the Aurora chat backend."),
+    paths(
+        routes::auth::get_login,
+        routes::auth::post_login,
+        routes::auth::post_logout,
+        routes::auth::post_register,
+        routes::auth::post_verify_email,
+        routes::auth::post_resend_verification,
+        routes::auth::post_password_reset,
+        routes::auth::post_password_reset_confirm,
+        routes::applications::get_applications,
+        routes::applications::post_application,
+        routes::applications::post_reset_bot_token,
+        routes::applications::get_oauth2_client,
+        routes::applications::put_oauth2_client,
+        routes::applications::post_reset_client_secret,
+        routes::oauth2::get_authorize,
+        routes::oauth2::post_authorize,
+        routes::oauth2::post_token,
+        routes::oauth2::post_revoke,
+        routes::users::get_me,
+        routes::errors::get_errors
+    ),
+    components(schemas(
+        JSONError,
+        FieldError,
+        OAuth2ErrorResponse,
+        routes::auth::LoginRequest,
+        routes::auth::RegisterRequest,
+        routes::auth::VerifyEmailRequest,
+        routes::auth::PasswordResetRequest,
+        routes::auth::PasswordResetConfirmRequest,
+        routes::applications::ApplicationResponse,
+        routes::applications::CreateApplicationRequest,
+        routes::applications::OAuth2ClientResponse,
+        routes::applications::OAuth2ClientRequest,
+        routes::oauth2::AuthorizationResponse,
+        routes::oauth2::ScopeDescription,
+        routes::oauth2::ConsentRequest,
+        routes::oauth2::ConsentResponse,
+        routes::oauth2::TokenRequest,
+        routes::oauth2::TokenResponse,
+        routes::oauth2::RevokeRequest,
+        routes::users::UserResponse,
+        routes::users::CurrentUserResponse,
+        routes::errors::ErrorDescription
+    )),
+    modifiers(&NoLicense, &SecuritySchemes, &ErrorCodes),
+    tags(
+        (name = "auth", description = "Authentication"),
+        (name = "applications", description = "Applications and their bots"),
+        (name = "oauth2", description = "OAuth2 authorization server for third-party apps"),
+        (name = "users", description = "Users"),
+        (name = "errors", description = "Error catalogue")
+    )
+)]
+pub struct ApiDoc;
+
+/// GET /api/v1/openapi.json - the OpenAPI document of the v1 API.
+pub async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// The crate has no license set, which would end up as an empty license object.
+struct NoLicense;
+
+impl Modify for NoLicense {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+    }
+}
+
+/// Registers the `Authorization: Bearer <token>`, `Authorization: Bot <token>`,
+/// session cookie and OAuth2 schemes.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+            components.add_security_scheme(
+                "bot",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                    "Authorization",
+                    "`Bot <token>`, with the token of an application.",
+                ))),
+            );
+            components.add_security_scheme(
+                "session",
+                SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                    session::SESSION_COOKIE,
+                    "Set by logging in with `cookie`. Mutating requests must repeat the \
+                     `aurora_csrf` cookie in the `X-CSRF-Token` header.",
+                ))),
+            );
+            components.add_security_scheme(
+                "oauth2",
+                SecurityScheme::OAuth2(OAuth2::with_description(
+                    [Flow::AuthorizationCode(AuthorizationCode::new(
+                        "/api/v1/oauth2/authorize",
+                        "/api/v1/oauth2/token",
+                        Scopes::from_iter(
+                            Scope::ALL.map(|scope| (scope.as_str(), scope.description())),
+                        ),
+                    ))],
+                    "Authorization code flow with PKCE (`S256`). The authorization endpoint \
+                     is served by the web app, which shows the consent screen.",
+                )),
+            );
+        }
+    }
+}
+
+/// Documents every [APIError] code on [JSONError].
+struct ErrorCodes;
+
+impl Modify for ErrorCodes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let Some(RefOr::T(Schema::Object(error))) = openapi
+            .components
+            .as_mut()
+            .and_then(|components| components.schemas.get_mut("JSONError"))
+        else {
+            return;
+        };
+
+        let Some(RefOr::T(Schema::Object(code))) = error.properties.get_mut("code") else {
+            return;
+        };
+
+        let examples = APIError::examples();
+        let codes = examples
+            .iter()
+            .map(|err| {
+                format!(
+                    "- `{}` ({}): {}",
+                    err.code(),
+                    err.status_code(),
+                    err.description()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        code.enum_values = Some(examples.iter().map(|err| err.code().into()).collect());
+        code.description = Some(format!(
+            "{}\n\n{codes}",
+            code.description.as_deref().unwrap_or_default()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_in_sync() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+
+        if std::env::var("UPDATE_OPENAPI").is_ok() {
+            std::fs::write(path, &spec).unwrap();
+            return;
+        }
+
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            committed == spec,
+            "openapi.json is out of date, regenerate it with `UPDATE_OPENAPI=1 cargo test`"
+        );
+    }
+
+    #[test]
+    fn test_openapi_documents_error_codes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let codes = &spec["components"]["schemas"]["JSONError"]["properties"]["code"]["enum"];
+
+        for err in APIError::examples() {
+            assert!(codes
+                .as_array()
+                .unwrap()
+                .contains(&serde_json::json!(err.code())));
+        }
+    }
+}
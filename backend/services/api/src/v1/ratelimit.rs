@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{Extensions, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+use tokio::time::{Duration, Instant};
+
+use super::{error::APIError, session};
+
+/// Once this many keys are tracked, expired windows are pruned on the next check.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Who a request is accounted to.
+///
+/// Routes anyone may call are keyed by the IP address of the peer, see [rate_limit].
+/// Authenticated routes are keyed by the user, once their token was verified, see
+/// [rate_limit_users].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    Ip(IpAddr),
+    User(u64),
+}
+
+impl RateLimitKey {
+    /// The IP address of the peer, set by `into_make_service_with_connect_info`.
+    fn ip(extensions: &Extensions) -> Option<Self> {
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| Self::Ip(addr.ip()))
+    }
+}
+
+/// Outcome of a single [RateLimiter::check].
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitStatus {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_after: Duration,
+}
+
+impl RateLimitStatus {
+    /// Writes the `X-RateLimit-*` headers describing this status.
+    pub fn apply_headers(&self, bucket: &'static str, headers: &mut HeaderMap) {
+        headers.insert("X-RateLimit-Limit", self.limit.into());
+        headers.insert("X-RateLimit-Remaining", self.remaining.into());
+        headers.insert("X-RateLimit-Bucket", HeaderValue::from_static(bucket));
+        headers.insert(
+            "X-RateLimit-Reset-After",
+            format!("{:.3}", self.reset_after.as_secs_f64())
+                .parse()
+                .unwrap(),
+        );
+    }
+
+    /// Seconds a client has to wait before retrying, rounded up.
+    pub fn retry_after(&self) -> u64 {
+        self.reset_after.as_secs() + u64::from(self.reset_after.subsec_nanos() > 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+/// Fixed window rate limiter for a group of routes (a bucket).
+///
+/// Each [RateLimitKey] may do `requests` requests per `window`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// The name of the bucket, reported through `X-RateLimit-Bucket`.
+    pub name: &'static str,
+
+    /// Amount of requests allowed per window.
+    pub requests: u32,
+
+    /// Length of a window.
+    pub window: Duration,
+
+    windows: Mutex<HashMap<RateLimitKey, Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(name: &'static str, requests: u32, window: Duration) -> Self {
+        Self {
+            name,
+            requests,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a rate limiter which can be configured through `RATELIMIT_<NAME>`.
+    ///
+    /// The variable has the format `<requests>/<seconds>`, e.g. `RATELIMIT_AUTH=5/60`.
+    /// If it is not set, the defaults are used.
+    pub fn from_env(name: &'static str, requests: u32, window: Duration) -> Self {
+        let env_var = format!("RATELIMIT_{}", name.to_uppercase());
+
+        let Ok(value) = std::env::var(&env_var) else {
+            return Self::new(name, requests, window);
+        };
+
+        let (requests, seconds) = value
+            .split_once('/')
+            .unwrap_or_else(|| panic!("{env_var} must be in the format '<requests>/<seconds>'"));
+
+        Self::new(
+            name,
+            requests
+                .trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("{env_var} requests must be a valid integer")),
+            Duration::from_secs(
+                seconds
+                    .trim()
+                    .parse()
+                    .unwrap_or_else(|_| panic!("{env_var} seconds must be a valid integer")),
+            ),
+        )
+    }
+
+    /// Account a request to `key` and report whether it may pass.
+    pub fn check(&self, key: RateLimitKey) -> RateLimitStatus {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: RateLimitKey, now: Instant) -> RateLimitStatus {
+        let mut windows = self.windows.lock().unwrap();
+
+        if windows.len() >= PRUNE_THRESHOLD {
+            windows.retain(|_, window| now.duration_since(window.started) < self.window);
+        }
+
+        let window = windows.entry(key).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+
+        if now.duration_since(window.started) >= self.window {
+            *window = Window {
+                started: now,
+                count: 0,
+            };
+        }
+
+        let allowed = window.count < self.requests;
+        if allowed {
+            window.count += 1;
+        }
+
+        RateLimitStatus {
+            allowed,
+            limit: self.requests,
+            remaining: self.requests - window.count,
+            reset_after: self.window - now.duration_since(window.started),
+        }
+    }
+}
+
+/// Middleware enforcing a [RateLimiter] per IP address, use it with
+/// [axum::middleware::from_fn_with_state].
+///
+/// Tokens are ignored, anyone can mint some by registering accounts. On routes which
+/// don't need one (e.g. logging in) they'd hand out a fresh bucket per token.
+///
+/// # Errors
+/// - [APIError::RateLimited] if the bucket of the caller is exhausted.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(key) = RateLimitKey::ip(request.extensions()) else {
+        return missing_peer_address();
+    };
+
+    enforce(&limiter, key, request, next).await
+}
+
+/// Like [rate_limit], but requests of authenticated users are accounted to the user.
+///
+/// Only tokens passing [session::verify] count, expired or revoked ones fall back to the IP.
+pub async fn rate_limit_users(
+    State((limiter, pool)): State<(Arc<RateLimiter>, PgPool)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    let key = match session::verify(&mut parts, &pool).await {
+        Ok(auth) => RateLimitKey::User(auth.token.user_id),
+        Err(_) => match RateLimitKey::ip(&parts.extensions) {
+            Some(key) => key,
+            None => return missing_peer_address(),
+        },
+    };
+
+    enforce(&limiter, key, Request::from_parts(parts, body), next).await
+}
+
+async fn enforce(
+    limiter: &RateLimiter,
+    key: RateLimitKey,
+    request: Request,
+    next: Next,
+) -> Response {
+    let status = limiter.check(key);
+
+    if !status.allowed {
+        warn!(bucket = limiter.name, ?key, "Rate limit exceeded");
+
+        let mut response = APIError::RateLimited {
+            retry_after: status.retry_after(),
+        }
+        .into_response();
+
+        status.apply_headers(limiter.name, response.headers_mut());
+
+        return response;
+    }
+
+    let mut response = next.run(request).await;
+    status.apply_headers(limiter.name, response.headers_mut());
+    response
+}
+
+/// Without the peer address every client would share a single bucket, refuse instead.
+fn missing_peer_address() -> Response {
+    error!("Missing peer address, serve the app with `into_make_service_with_connect_info`");
+
+    APIError::GenericError(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Internal server error.".into(),
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::token::AuthenticationToken;
+    use axum::{body::Body, routing::post, Router};
+    use sqlx::postgres::PgPoolOptions;
+    use std::net::Ipv4Addr;
+    use tower::ServiceExt;
+    use tracing_test::traced_test;
+
+    const LOCALHOST: RateLimitKey = RateLimitKey::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ratelimit_exhaustion() {
+        let limiter = RateLimiter::new("test", 2, Duration::from_secs(10));
+        let now = Instant::now();
+
+        let status = limiter.check_at(LOCALHOST, now);
+        assert!(status.allowed);
+        assert_eq!(status.remaining, 1);
+
+        let status = limiter.check_at(LOCALHOST, now);
+        assert!(status.allowed);
+        assert_eq!(status.remaining, 0);
+
+        let status = limiter.check_at(LOCALHOST, now + Duration::from_millis(2500));
+        assert!(!status.allowed);
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.reset_after, Duration::from_millis(7500));
+        assert_eq!(status.retry_after(), 8);
+
+        // Other keys have their own window.
+        assert!(limiter.check_at(RateLimitKey::User(1), now).allowed);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ratelimit_window_reset() {
+        let limiter = RateLimiter::new("test", 1, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(limiter.check_at(LOCALHOST, now).allowed);
+        assert!(!limiter.check_at(LOCALHOST, now).allowed);
+        assert!(
+            limiter
+                .check_at(LOCALHOST, now + Duration::from_secs(10))
+                .allowed
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ratelimit_from_env() {
+        std::env::set_var("RATELIMIT_FROM_ENV_TEST", "3/15");
+
+        let limiter = RateLimiter::from_env("from_env_test", 1, Duration::from_secs(1));
+        assert_eq!(limiter.requests, 3);
+        assert_eq!(limiter.window, Duration::from_secs(15));
+
+        let limiter = RateLimiter::from_env("unset_test", 1, Duration::from_secs(1));
+        assert_eq!(limiter.requests, 1);
+        assert_eq!(limiter.window, Duration::from_secs(1));
+    }
+
+    fn request(uri: &str, peer: [u8; 4], token: Option<&str>) -> Request<Body> {
+        let mut request = Request::post(uri);
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {token}"));
+        }
+
+        let mut request = request.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((peer, 1234))));
+        request
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ratelimit_middleware() {
+        let limiter = Arc::new(RateLimiter::new("test", 1, Duration::from_secs(60)));
+        let app = Router::new()
+            .route("/", post(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn_with_state(limiter, rate_limit));
+
+        let response = app
+            .clone()
+            .oneshot(request("/", [127, 0, 0, 1], None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["X-RateLimit-Limit"], "1");
+        assert_eq!(response.headers()["X-RateLimit-Remaining"], "0");
+        assert_eq!(response.headers()["X-RateLimit-Bucket"], "test");
+
+        let response = app
+            .clone()
+            .oneshot(request("/", [127, 0, 0, 1], None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["Retry-After"], "60");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], 30001);
+
+        // Other IPs have their own bucket.
+        let response = app
+            .oneshot(request("/", [10, 0, 0, 1], None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ratelimit_ignores_tokens_of_anonymous_routes() {
+        std::env::set_var("HMAC_SECURITY_KEY", "TODO: secret key");
+
+        let limiter = Arc::new(RateLimiter::new("auth", 1, Duration::from_secs(60)));
+        let app = Router::new()
+            .route("/auth/login", post(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn_with_state(limiter, rate_limit));
+
+        // A fresh, valid token per attempt doesn't get a fresh bucket.
+        for (user_id, status) in [(1, StatusCode::OK), (2, StatusCode::TOO_MANY_REQUESTS)] {
+            let token: String = AuthenticationToken::new(user_id).unwrap().into();
+            let response = app
+                .clone()
+                .oneshot(request("/auth/login", [127, 0, 0, 1], Some(&token)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ratelimit_users_falls_back_to_ip() {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://aurora@127.0.0.1:1/aurora")
+            .unwrap();
+        let limiter = Arc::new(RateLimiter::new("test", 1, Duration::from_secs(60)));
+        let app = Router::new()
+            .route("/", post(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn_with_state(
+                (limiter, pool),
+                rate_limit_users,
+            ));
+
+        // Tokens which don't verify count against the IP.
+        for (token, status) in [
+            ("invalid.token", StatusCode::OK),
+            ("another.invalid.token", StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(request("/", [127, 0, 0, 1], Some(token)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_ratelimit_without_peer_address() {
+        let limiter = Arc::new(RateLimiter::new("test", 1, Duration::from_secs(60)));
+        let app = Router::new()
+            .route("/", post(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn_with_state(limiter, rate_limit));
+
+        let response = app
+            .oneshot(Request::post("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(logs_contain("Missing peer address"));
+    }
+}
@@ -0,0 +1,353 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sqlx::PgPool;
+
+use crate::{
+    models::{
+        oauth2::Client,
+        user::{self, User},
+    },
+    v1::{
+        error::{APIError, APIResult},
+        oauth2::{generate_secret, hash_secret, validate_redirect_uri},
+        routes::users::UserResponse,
+        session::Authenticated,
+        token::AuthenticationToken,
+        validation::{trimmed, FieldError, FieldErrors, ValidatedJson},
+    },
+};
+
+/// An application of a user, which is a bot account.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct ApplicationResponse {
+    /// Also the ID of the bot.
+    pub id: i64,
+    pub owner_id: i64,
+    pub bot: UserResponse,
+
+    /// Token of the bot, for `Authorization: Bot <token>`.
+    /// Only returned on creation and regeneration, store it safely.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl ApplicationResponse {
+    fn new(bot: User, token: Option<AuthenticationToken>) -> Self {
+        Self {
+            id: bot.id,
+            owner_id: bot.owner_id.unwrap_or_default(),
+            bot: bot.into(),
+            token: token.map(String::from),
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct CreateApplicationRequest {
+    /// The username of the bot.
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 2, max = 32))]
+    pub name: String,
+}
+
+/// GET /api/v1/applications - lists the applications of the current user.
+#[utoipa::path(
+    get,
+    path = "/api/v1/applications",
+    tag = "applications",
+    security(("bearer" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "The applications, oldest first.", body = [ApplicationResponse]),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn get_applications(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+) -> APIResult<Json<Vec<ApplicationResponse>>> {
+    let auth = auth.deny_bots()?;
+
+    let bots = User::find_bots(&pool, auth.token.user_id as i64).await?;
+
+    Ok(Json(
+        bots.into_iter()
+            .map(|bot| ApplicationResponse::new(bot, None))
+            .collect(),
+    ))
+}
+
+/// POST /api/v1/applications - creates an application, and its bot.
+#[utoipa::path(
+    post,
+    path = "/api/v1/applications",
+    tag = "applications",
+    security(("bearer" = []), ("session" = [])),
+    request_body = CreateApplicationRequest,
+    responses(
+        (status = 200, description = "The application, with the token of its bot.", body = ApplicationResponse),
+        (status = 400, description = "Invalid form body, or the name is taken (50035).", body = JSONError),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001), or missing CSRF token (40007).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn post_application(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+    ValidatedJson(request): ValidatedJson<CreateApplicationRequest>,
+) -> APIResult<Json<ApplicationResponse>> {
+    let auth = auth.deny_bots()?;
+    let owner_id = auth.token.user_id as i64;
+
+    let bot = match User::create_bot(&pool, owner_id, &request.name).await {
+        Ok(bot) => bot,
+        Err(err) if user::taken_field(&err).is_some() => {
+            return Err(APIError::InvalidFormBody {
+                errors: FieldErrors::from([(
+                    "name".to_owned(),
+                    vec![FieldError::new("taken", "This name is taken.")],
+                )]),
+            });
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    info!(owner_id, bot_id = bot.id, "Application created");
+
+    let token = AuthenticationToken::new(bot.id as u64)?;
+    Ok(Json(ApplicationResponse::new(bot, Some(token))))
+}
+
+/// POST /api/v1/applications/{id}/bot/reset - regenerates the token of the bot.
+///
+/// The previous token stops working immediately.
+#[utoipa::path(
+    post,
+    path = "/api/v1/applications/{id}/bot/reset",
+    tag = "applications",
+    security(("bearer" = []), ("session" = [])),
+    params(("id" = i64, Path, description = "ID of the application")),
+    responses(
+        (status = 200, description = "The application, with the new token of its bot.", body = ApplicationResponse),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001), or missing CSRF token (40007).", body = JSONError),
+        (status = 404, description = "Unknown application (10002).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn post_reset_bot_token(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+    Path(id): Path<i64>,
+) -> APIResult<Json<ApplicationResponse>> {
+    let auth = auth.deny_bots()?;
+    let owner_id = auth.token.user_id as i64;
+
+    let bot = find_application(&pool, owner_id, id).await?;
+
+    // Revoked by the clock of the API, so the token generated after it is valid.
+    bot.revoke_sessions(&pool).await?;
+
+    info!(owner_id, bot_id = bot.id, "Bot token regenerated");
+
+    let token = AuthenticationToken::new(bot.id as u64)?;
+    Ok(Json(ApplicationResponse::new(bot, Some(token))))
+}
+
+/// The bot of the application `id`, if it is owned by `owner_id`.
+async fn find_application(pool: &PgPool, owner_id: i64, id: i64) -> APIResult<User> {
+    User::find_by_id(pool, id)
+        .await?
+        .filter(|bot| bot.owner_id == Some(owner_id))
+        .ok_or(APIError::UnknownApplication)
+}
+
+/// The OAuth2 client registration of an application, see [crate::v1::oauth2].
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct OAuth2ClientResponse {
+    /// The ID of the application.
+    pub client_id: i64,
+    pub redirect_uris: Vec<String>,
+
+    /// Whether the client authenticates with a secret.
+    pub confidential: bool,
+
+    /// Only returned when a secret is issued, store it safely.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+impl OAuth2ClientResponse {
+    fn new(client: Client, client_secret: Option<String>) -> Self {
+        Self {
+            client_id: client.application_id,
+            confidential: client.is_confidential(),
+            redirect_uris: client.redirect_uris,
+            client_secret,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct OAuth2ClientRequest {
+    /// Where users may be sent back to after authorizing, compared exactly.
+    #[validate(length(min = 1, max = 10))]
+    pub redirect_uris: Vec<String>,
+
+    /// Whether the application can keep a secret, e.g. because it runs on a server.
+    /// Public clients, like single page or native apps, rely on PKCE alone.
+    pub confidential: bool,
+}
+
+/// GET /api/v1/applications/{id}/oauth2 - the OAuth2 client registration of an application.
+#[utoipa::path(
+    get,
+    path = "/api/v1/applications/{id}/oauth2",
+    tag = "applications",
+    security(("bearer" = []), ("session" = [])),
+    params(("id" = i64, Path, description = "ID of the application")),
+    responses(
+        (status = 200, description = "The registration.", body = OAuth2ClientResponse),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001).", body = JSONError),
+        (status = 404, description = "Unknown application, or not registered (10002).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn get_oauth2_client(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+    Path(id): Path<i64>,
+) -> APIResult<Json<OAuth2ClientResponse>> {
+    let auth = auth.deny_bots()?;
+    let bot = find_application(&pool, auth.token.user_id as i64, id).await?;
+
+    let client = Client::find(&pool, bot.id)
+        .await?
+        .ok_or(APIError::UnknownApplication)?;
+
+    Ok(Json(OAuth2ClientResponse::new(client, None)))
+}
+
+/// PUT /api/v1/applications/{id}/oauth2 - registers an application as OAuth2 client,
+///                                       or updates its registration.
+///
+/// A secret is issued when the client becomes confidential, it is kept on later updates.
+#[utoipa::path(
+    put,
+    path = "/api/v1/applications/{id}/oauth2",
+    tag = "applications",
+    security(("bearer" = []), ("session" = [])),
+    params(("id" = i64, Path, description = "ID of the application")),
+    request_body = OAuth2ClientRequest,
+    responses(
+        (status = 200, description = "The registration, with the client secret if one was issued.", body = OAuth2ClientResponse),
+        (status = 400, description = "Invalid form body (50035).", body = JSONError),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001), or missing CSRF token (40007).", body = JSONError),
+        (status = 404, description = "Unknown application (10002).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn put_oauth2_client(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+    Path(id): Path<i64>,
+    ValidatedJson(request): ValidatedJson<OAuth2ClientRequest>,
+) -> APIResult<Json<OAuth2ClientResponse>> {
+    let auth = auth.deny_bots()?;
+    let bot = find_application(&pool, auth.token.user_id as i64, id).await?;
+
+    let errors: FieldErrors = request
+        .redirect_uris
+        .iter()
+        .enumerate()
+        .filter_map(|(index, uri)| {
+            let message = validate_redirect_uri(uri).err()?;
+            Some((
+                format!("redirect_uris[{index}]"),
+                vec![FieldError::new("redirect_uri", message)],
+            ))
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(APIError::InvalidFormBody { errors });
+    }
+
+    let current_secret = Client::find(&pool, bot.id)
+        .await?
+        .and_then(|client| client.secret_hash);
+
+    let (secret_hash, client_secret) = match (request.confidential, current_secret) {
+        (false, _) => (None, None),
+        (true, Some(secret_hash)) => (Some(secret_hash), None),
+        (true, None) => {
+            let secret = generate_secret();
+            (Some(hash_secret(&secret)), Some(secret))
+        }
+    };
+
+    let client = Client::save(
+        &pool,
+        bot.id,
+        &request.redirect_uris,
+        secret_hash.as_deref(),
+    )
+    .await?;
+
+    info!(
+        application_id = bot.id,
+        confidential = request.confidential,
+        "OAuth2 client registered"
+    );
+
+    Ok(Json(OAuth2ClientResponse::new(client, client_secret)))
+}
+
+/// POST /api/v1/applications/{id}/oauth2/secret/reset - issues a new client secret.
+///
+/// The previous secret stops working immediately, public clients become confidential.
+#[utoipa::path(
+    post,
+    path = "/api/v1/applications/{id}/oauth2/secret/reset",
+    tag = "applications",
+    security(("bearer" = []), ("session" = [])),
+    params(("id" = i64, Path, description = "ID of the application")),
+    responses(
+        (status = 200, description = "The registration, with the new client secret.", body = OAuth2ClientResponse),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001), or missing CSRF token (40007).", body = JSONError),
+        (status = 404, description = "Unknown application, or not registered (10002).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn post_reset_client_secret(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+    Path(id): Path<i64>,
+) -> APIResult<Json<OAuth2ClientResponse>> {
+    let auth = auth.deny_bots()?;
+    let bot = find_application(&pool, auth.token.user_id as i64, id).await?;
+
+    let client = Client::find(&pool, bot.id)
+        .await?
+        .ok_or(APIError::UnknownApplication)?;
+
+    let secret = generate_secret();
+    let client = Client::save(
+        &pool,
+        bot.id,
+        &client.redirect_uris,
+        Some(&hash_secret(&secret)),
+    )
+    .await?;
+
+    info!(application_id = bot.id, "OAuth2 client secret regenerated");
+
+    Ok(Json(OAuth2ClientResponse::new(client, Some(secret))))
+}
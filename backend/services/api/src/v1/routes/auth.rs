@@ -1,32 +1,419 @@
-use axum::http::{HeaderMap, StatusCode};
+use std::net::SocketAddr;
 
-use crate::v1::{
-    error::{APIError, APIResult},
-    token::AuthenticationToken,
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+
+use crate::{
+    jobs,
+    mail::ActionMail,
+    models::{login_throttle, user},
+    v1::{
+        action_token::{Action, ActionToken},
+        error::{APIError, APIResult},
+        session::{self, Authenticated, Credentials},
+        token::{AuthenticationToken, TokenError},
+        validation::{trimmed, validate_password, FieldError, FieldErrors, ValidatedJson},
+    },
 };
 
 /// GET /api/v1/auth/login - used to refresh a token. It must be called every login.
 ///                          returns a new token; The old one is valid until it expires.
+///                          cookie sessions get new cookies instead.
 ///
-#[axum::debug_handler]
-pub async fn get_login(headers: HeaderMap) -> APIResult<String> {
-    let token = AuthenticationToken::from_headers(&headers)?;
-    if token.expired() {
-        return Err(APIError::ExpiredToken);
-    }
-
-    // TODO: store it in some kind of database to check for revocation
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/login",
+    tag = "auth",
+    security(("bearer" = []), ("session" = [])),
+    responses(
+        (status = 200, description = "A fresh token.", body = String, content_type = "text/plain"),
+        (status = 204, description = "Renewed the session cookies."),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001).", body = JSONError),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn get_login(auth: Authenticated) -> APIResult<Response> {
+    let Authenticated { token, credentials } = auth.deny_bots()?;
 
-    // otherwise, we can now refresh the token
+    // Revoked tokens were rejected already, see [Authenticated].
     let new_token = token.refresh();
-    Ok(new_token.into())
+
+    Ok(match credentials {
+        Credentials::Header | Credentials::Bot => String::from(new_token).into_response(),
+        Credentials::Cookie => (
+            StatusCode::NO_CONTENT,
+            session::session_cookies(&new_token)?,
+        )
+            .into_response(),
+    })
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct LoginRequest {
+    /// The username of the account.
+    #[validate(length(min = 1, max = 256))]
+    pub login: String,
+
+    #[validate(length(min = 1, max = 1024))]
+    pub password: String,
+
+    /// Start a cookie session instead of returning the token, see the `session` scheme.
+    #[serde(default)]
+    pub cookie: bool,
 }
 
 /// POST /api/v1/auth/login - used to authenticate a user through Username/Password
 ///                           may have multiple stages (e.g. 2FA)
-pub async fn post_login() -> APIResult<String> {
-    let token = AuthenticationToken::new(183718260674527232).unwrap();
+///
+/// Failed attempts are throttled per account and per IP, see [login_throttle].
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "A token for the account.", body = String, content_type = "text/plain"),
+        (status = 204, description = "Started a cookie session, when `cookie` is set."),
+        (status = 400, description = "Invalid form body (50035).", body = JSONError),
+        (status = 401, description = "Invalid login or password (40005).", body = JSONError),
+        (status = 429, description = "Rate limited (30001) or cooling down after failed logins (40006).", body = JSONError),
+    )
+)]
+#[axum::debug_handler]
+pub async fn post_login(
+    State(pool): State<PgPool>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ValidatedJson(request): ValidatedJson<LoginRequest>,
+) -> APIResult<Response> {
+    let login = request.login.trim().to_lowercase();
+    let ip = addr.ip();
+
+    if let Some(retry_after) = login_throttle::cooldown(&pool, &login, ip).await? {
+        return Err(APIError::LoginCooldown { retry_after });
+    }
+
+    let user = user::User::find_by_username(&pool, &login).await?;
+    if !user::verify_password(user.as_ref(), request.password).await {
+        login_throttle::record_failure(&pool, &login, ip).await?;
+        return Err(APIError::InvalidCredentials);
+    }
+
+    login_throttle::reset(&pool, &login).await?;
+
+    // verify_password never succeeds without a user.
+    let user = user.ok_or(APIError::InvalidCredentials)?;
+    let token = AuthenticationToken::new(user.id as u64)?;
+
+    info!(
+        user_id = user.id,
+        username = user.username,
+        cookie = request.cookie,
+        "User logged in"
+    );
+
+    if request.cookie {
+        return Ok((StatusCode::NO_CONTENT, session::session_cookies(&token)?).into_response());
+    }
+
+    Ok(String::from(token).into_response())
+}
+
+/// POST /api/v1/auth/logout - ends a cookie session by clearing its cookies.
+///                            bearer tokens stay valid until they expire.
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/logout",
+    tag = "auth",
+    security(("bearer" = []), ("session" = [])),
+    responses(
+        (status = 204, description = "Cleared the session cookies."),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001), or missing CSRF token (40007).", body = JSONError),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn post_logout(auth: Authenticated) -> APIResult<impl IntoResponse> {
+    auth.deny_bots()?;
+
+    Ok((StatusCode::NO_CONTENT, session::clear_session_cookies()))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct RegisterRequest {
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 2, max = 32))]
+    pub username: String,
+
+    /// Has to be verified through the link mailed to it.
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(email, length(max = 254))]
+    pub email: String,
+
+    #[validate(custom(function = "validate_password"))]
+    pub password: String,
+}
 
-    // TODO: implement real login
+/// POST /api/v1/auth/register - creates an account, and mails a link to verify its email.
+///                              returns a token, like a login.
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/register",
+    tag = "auth",
+    request_body = RegisterRequest,
+    responses(
+        (status = 200, description = "A token for the new account.", body = String, content_type = "text/plain"),
+        (status = 400, description = "Invalid form body, or the username or email is taken (50035).", body = JSONError),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler]
+pub async fn post_register(
+    State(pool): State<PgPool>,
+    ValidatedJson(request): ValidatedJson<RegisterRequest>,
+) -> APIResult<String> {
+    let password_hash = hash_password(request.password).await?;
+
+    let user = match user::User::create(
+        &pool,
+        &request.username,
+        Some(&request.email),
+        &password_hash,
+    )
+    .await
+    {
+        Ok(user) => user,
+        Err(err) => {
+            let Some(field) = user::taken_field(&err) else {
+                return Err(err.into());
+            };
+
+            return Err(APIError::InvalidFormBody {
+                errors: FieldErrors::from([(
+                    field.to_owned(),
+                    vec![FieldError::new("taken", format!("This {field} is taken."))],
+                )]),
+            });
+        }
+    };
+
+    info!(
+        user_id = user.id,
+        username = user.username,
+        "User registered"
+    );
+
+    send_verification_mail(&pool, &user).await?;
+
+    let token = AuthenticationToken::new(user.id as u64)?;
     Ok(token.into())
 }
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct VerifyEmailRequest {
+    /// The token from the link in the mail.
+    #[validate(length(max = 512))]
+    pub token: String,
+}
+
+/// POST /api/v1/auth/verify-email - verifies the email of an account, with the token mailed to it.
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/verify-email",
+    tag = "auth",
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 204, description = "The email is verified."),
+        (status = 400, description = "Invalid, expired or used token (40008).", body = JSONError),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler]
+pub async fn post_verify_email(
+    State(pool): State<PgPool>,
+    ValidatedJson(request): ValidatedJson<VerifyEmailRequest>,
+) -> APIResult<StatusCode> {
+    let token = ActionToken::parse(&request.token, Action::VerifyEmail)
+        .map_err(|_| APIError::InvalidActionToken)?;
+
+    let user = user::User::find_by_id(&pool, token.user_id as i64)
+        .await?
+        .ok_or(APIError::InvalidActionToken)?;
+
+    // Bound to the email, a link for a previous address is useless.
+    let email = user.email.as_deref().ok_or(APIError::InvalidActionToken)?;
+    token
+        .verify(&email.to_lowercase())
+        .map_err(|_| APIError::InvalidActionToken)?;
+
+    if !user.verify_email(&pool).await? {
+        return Err(APIError::InvalidActionToken);
+    }
+
+    info!(user_id = user.id, "Email verified");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /api/v1/auth/verify-email/resend - mails a new link to verify the email of the account.
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/verify-email/resend",
+    tag = "auth",
+    security(("bearer" = []), ("session" = [])),
+    responses(
+        (status = 202, description = "A link was mailed, unless the email is verified already."),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001), or missing CSRF token (40007).", body = JSONError),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn post_resend_verification(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+) -> APIResult<StatusCode> {
+    let Authenticated { token, .. } = auth.deny_bots()?;
+
+    let user = user::User::find_by_id(&pool, token.user_id as i64)
+        .await?
+        .ok_or(APIError::InvalidToken(TokenError::InvalidToken))?;
+
+    if !user.email_verified {
+        send_verification_mail(&pool, &user).await?;
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct PasswordResetRequest {
+    /// The email of the account.
+    #[serde(deserialize_with = "trimmed")]
+    #[validate(length(min = 1, max = 254))]
+    pub email: String,
+}
+
+/// POST /api/v1/auth/password-reset - mails a link to reset the password of an account.
+///
+/// Always accepted, so it can't be used to find out whether an email has an account.
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/password-reset",
+    tag = "auth",
+    request_body = PasswordResetRequest,
+    responses(
+        (status = 202, description = "If the email belongs to an account, a link was mailed to it."),
+        (status = 400, description = "Invalid form body (50035).", body = JSONError),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler]
+pub async fn post_password_reset(
+    State(pool): State<PgPool>,
+    ValidatedJson(request): ValidatedJson<PasswordResetRequest>,
+) -> APIResult<StatusCode> {
+    let Some(user) = user::User::find_by_email(&pool, &request.email).await? else {
+        return Ok(StatusCode::ACCEPTED);
+    };
+    jobs::enqueue(
+        &pool,
+        &ActionMail {
+            action: Action::ResetPassword,
+            user_id: user.id,
+        },
+    )
+    .await?;
+
+    info!(user_id = user.id, "Password reset requested");
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct PasswordResetConfirmRequest {
+    /// The token from the link in the mail.
+    #[validate(length(max = 512))]
+    pub token: String,
+
+    /// The new password.
+    #[validate(custom(function = "validate_password"))]
+    pub password: String,
+}
+
+/// POST /api/v1/auth/password-reset/confirm - sets a new password, with the token mailed by
+///                                            `/auth/password-reset`.
+///
+/// Every token issued for the account until now is revoked.
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/password-reset/confirm",
+    tag = "auth",
+    request_body = PasswordResetConfirmRequest,
+    responses(
+        (status = 204, description = "The password was changed, log in again."),
+        (status = 400, description = "Invalid form body (50035), or invalid, expired or used token (40008).", body = JSONError),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler]
+pub async fn post_password_reset_confirm(
+    State(pool): State<PgPool>,
+    ValidatedJson(request): ValidatedJson<PasswordResetConfirmRequest>,
+) -> APIResult<StatusCode> {
+    let token = ActionToken::parse(&request.token, Action::ResetPassword)
+        .map_err(|_| APIError::InvalidActionToken)?;
+
+    let user = user::User::find_by_id(&pool, token.user_id as i64)
+        .await?
+        .ok_or(APIError::InvalidActionToken)?;
+
+    token
+        .verify(&user.password_hash)
+        .map_err(|_| APIError::InvalidActionToken)?;
+
+    let password_hash = hash_password(request.password).await?;
+    user.reset_password(&pool, &password_hash).await?;
+
+    info!(user_id = user.id, "Password reset, sessions revoked");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Mail a link to verify the email of `user`, if they have one.
+async fn send_verification_mail(pool: &PgPool, user: &user::User) -> APIResult<()> {
+    if user.email.is_none() {
+        return Ok(());
+    }
+
+    jobs::enqueue(
+        pool,
+        &ActionMail {
+            action: Action::VerifyEmail,
+            user_id: user.id,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Hash a password on the blocking thread pool, it is expensive.
+async fn hash_password(password: String) -> APIResult<String> {
+    tokio::task::spawn_blocking(move || user::hash_password(&password))
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .ok_or_else(|| {
+            APIError::GenericError(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error.".into(),
+            )
+        })
+}
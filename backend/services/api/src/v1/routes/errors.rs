@@ -0,0 +1,44 @@
+use axum::Json;
+
+use crate::v1::error::APIError;
+
+/// An entry of the error catalogue.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct ErrorDescription {
+    /// The code sent as `code` in error responses.
+    pub code: u64,
+
+    /// The HTTP status the error is answered with.
+    pub status: u16,
+
+    /// What the error means.
+    pub description: &'static str,
+
+    /// An example message, the details vary per occurrence.
+    pub message: String,
+}
+
+/// GET /api/v1/errors - lists every error code the API may answer with.
+#[utoipa::path(
+    get,
+    path = "/api/v1/errors",
+    tag = "errors",
+    responses(
+        (status = 200, description = "Every error code, ordered by code.", body = [ErrorDescription]),
+    )
+)]
+pub async fn get_errors() -> Json<Vec<ErrorDescription>> {
+    let mut errors: Vec<ErrorDescription> = APIError::examples()
+        .into_iter()
+        .map(|err| ErrorDescription {
+            code: err.code(),
+            status: err.status_code().as_u16(),
+            description: err.description(),
+            message: err.to_string(),
+        })
+        .collect();
+
+    errors.sort_by_key(|err| err.code);
+
+    Json(errors)
+}
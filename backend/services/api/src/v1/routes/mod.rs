@@ -1 +1,5 @@
+pub mod applications;
 pub mod auth;
+pub mod errors;
+pub mod oauth2;
+pub mod users;
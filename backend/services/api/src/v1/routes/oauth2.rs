@@ -0,0 +1,640 @@
+use axum::{
+    extract::{rejection::FormRejection, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Form, Json,
+};
+use base64::prelude::*;
+use sqlx::PgPool;
+
+use crate::{
+    models::{
+        oauth2::{self, Client},
+        user::User,
+    },
+    v1::{
+        error::{APIError, APIResult},
+        oauth2::{
+            generate_secret, hash_secret, no_store, redirect_location, verify_pkce, verify_secret,
+            OAuth2Error, Scopes,
+        },
+        routes::users::UserResponse,
+        session::Authenticated,
+        token::{AuthenticationToken, Grant, TOKEN_EXPIRATION_TIME},
+        validation::{FieldError, FieldErrors, ValidatedJson, ValidatedQuery},
+    },
+};
+
+/// The parameters an application sends the user to the consent screen with.
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams, validator::Validate)]
+#[into_params(parameter_in = Query)]
+pub struct AuthorizeRequest {
+    /// Must be `code`.
+    pub response_type: String,
+
+    /// The ID of the application.
+    pub client_id: i64,
+
+    /// One of the registered redirect URIs, may be left out if only one is registered.
+    pub redirect_uri: Option<String>,
+
+    /// Space separated scopes, e.g. `identify email`.
+    pub scope: String,
+
+    /// Handed back to the application unchanged.
+    #[validate(length(max = 1024))]
+    pub state: Option<String>,
+
+    /// `BASE64URL(SHA256(code_verifier))`, see RFC 7636.
+    #[validate(length(equal = 43))]
+    pub code_challenge: String,
+
+    /// Must be `S256`.
+    pub code_challenge_method: String,
+}
+
+/// What the consent screen shows.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct AuthorizationResponse {
+    /// The application asking for access, as its bot.
+    pub application: UserResponse,
+
+    /// The requested scopes.
+    pub scopes: Vec<ScopeDescription>,
+
+    /// Where the user is sent back to.
+    pub redirect_uri: String,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct ScopeDescription {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// An [AuthorizeRequest] which passed validation.
+struct ValidAuthorization {
+    application: User,
+    redirect_uri: String,
+    scopes: Scopes,
+}
+
+fn invalid_field(field: &str, code: &str, message: impl Into<String>) -> APIError {
+    APIError::InvalidFormBody {
+        errors: FieldErrors::from([(field.to_owned(), vec![FieldError::new(code, message)])]),
+    }
+}
+
+/// Check an authorization request against the registration of its client.
+async fn validate_authorization(
+    pool: &PgPool,
+    request: &AuthorizeRequest,
+) -> APIResult<ValidAuthorization> {
+    let client = Client::find(pool, request.client_id)
+        .await?
+        .ok_or(APIError::UnknownApplication)?;
+
+    let application = User::find_by_id(pool, client.application_id)
+        .await?
+        .ok_or(APIError::UnknownApplication)?;
+
+    // Never redirect anywhere unregistered, the user would be sent to an attacker.
+    let redirect_uri = match (&request.redirect_uri, client.redirect_uris.as_slice()) {
+        (Some(uri), registered) if registered.contains(uri) => uri.clone(),
+        (None, [only]) => only.clone(),
+        _ => {
+            return Err(invalid_field(
+                "redirect_uri",
+                "unregistered",
+                "Not a registered redirect URI.",
+            ))
+        }
+    };
+
+    if request.response_type != "code" {
+        return Err(invalid_field(
+            "response_type",
+            "unsupported",
+            "Must be `code`.",
+        ));
+    }
+
+    if request.code_challenge_method != "S256" {
+        return Err(invalid_field(
+            "code_challenge_method",
+            "unsupported",
+            "Must be `S256`.",
+        ));
+    }
+
+    let scopes = match Scopes::parse(&request.scope) {
+        Ok(scopes) if !scopes.is_empty() => scopes,
+        Ok(_) => {
+            return Err(invalid_field(
+                "scope",
+                "invalid_scope",
+                "At least one scope is required.",
+            ))
+        }
+        Err(unknown) => {
+            return Err(invalid_field(
+                "scope",
+                "invalid_scope",
+                format!("Unknown scope '{unknown}'."),
+            ))
+        }
+    };
+
+    Ok(ValidAuthorization {
+        application,
+        redirect_uri,
+        scopes,
+    })
+}
+
+/// GET /api/v1/oauth2/authorize - what an application asks for, to show the consent screen.
+#[utoipa::path(
+    get,
+    path = "/api/v1/oauth2/authorize",
+    tag = "oauth2",
+    security(("bearer" = []), ("session" = [])),
+    params(AuthorizeRequest),
+    responses(
+        (status = 200, description = "The application and the requested scopes.", body = AuthorizationResponse),
+        (status = 400, description = "Invalid parameters, or an unregistered redirect URI (50035).", body = JSONError),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001).", body = JSONError),
+        (status = 404, description = "Unknown application (10002).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn get_authorize(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+    ValidatedQuery(request): ValidatedQuery<AuthorizeRequest>,
+) -> APIResult<Json<AuthorizationResponse>> {
+    auth.deny_bots()?;
+
+    let authorization = validate_authorization(&pool, &request).await?;
+
+    Ok(Json(AuthorizationResponse {
+        application: authorization.application.into(),
+        scopes: authorization
+            .scopes
+            .iter()
+            .map(|scope| ScopeDescription {
+                name: scope.as_str(),
+                description: scope.description(),
+            })
+            .collect(),
+        redirect_uri: authorization.redirect_uri,
+    }))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema, validator::Validate)]
+pub struct ConsentRequest {
+    /// Whether the user allowed access.
+    pub allow: bool,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct ConsentResponse {
+    /// Where to send the browser, back to the application with a code or an error.
+    pub location: String,
+}
+
+/// POST /api/v1/oauth2/authorize - allows (or denies) an application access.
+///
+/// Takes the same parameters as `GET /api/v1/oauth2/authorize`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/oauth2/authorize",
+    tag = "oauth2",
+    security(("bearer" = []), ("session" = [])),
+    params(AuthorizeRequest),
+    request_body = ConsentRequest,
+    responses(
+        (status = 200, description = "Where to redirect the user to.", body = ConsentResponse),
+        (status = 400, description = "Invalid parameters, or an unregistered redirect URI (50035).", body = JSONError),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "Bots cannot use this endpoint (20001), or missing CSRF token (40007).", body = JSONError),
+        (status = 404, description = "Unknown application (10002).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn post_authorize(
+    State(pool): State<PgPool>,
+    auth: Authenticated,
+    ValidatedQuery(request): ValidatedQuery<AuthorizeRequest>,
+    ValidatedJson(consent): ValidatedJson<ConsentRequest>,
+) -> APIResult<Json<ConsentResponse>> {
+    let auth = auth.deny_bots()?;
+    let authorization = validate_authorization(&pool, &request).await?;
+
+    let code = consent.allow.then(generate_secret);
+    let mut params = Vec::new();
+
+    match &code {
+        Some(code) => {
+            oauth2::create_code(
+                &pool,
+                &hash_secret(code),
+                authorization.application.id,
+                auth.token.user_id as i64,
+                &authorization.scopes.to_string(),
+                request.redirect_uri.as_deref(),
+                &request.code_challenge,
+            )
+            .await?;
+
+            params.push(("code", code.as_str()));
+        }
+        None => params.push(("error", "access_denied")),
+    }
+
+    if let Some(state) = &request.state {
+        params.push(("state", state));
+    }
+
+    info!(
+        user_id = auth.token.user_id,
+        application_id = authorization.application.id,
+        allow = consent.allow,
+        "User answered OAuth2 consent"
+    );
+
+    Ok(Json(ConsentResponse {
+        location: redirect_location(&authorization.redirect_uri, &params),
+    }))
+}
+
+/// Parameters of the token endpoint, form encoded.
+///
+/// Clients authenticate with HTTP Basic, or with `client_id` and `client_secret`.
+/// Public clients send their `client_id` only.
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+pub struct TokenRequest {
+    /// `authorization_code` or `refresh_token`.
+    pub grant_type: String,
+
+    /// For `authorization_code`.
+    pub code: Option<String>,
+
+    /// For `authorization_code`, if it was sent to the authorization endpoint.
+    pub redirect_uri: Option<String>,
+
+    /// For `authorization_code`, the PKCE verifier.
+    pub code_verifier: Option<String>,
+
+    /// For `refresh_token`.
+    pub refresh_token: Option<String>,
+
+    /// For `refresh_token`, to narrow the scopes of the new access token.
+    pub scope: Option<String>,
+
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct TokenResponse {
+    /// For `Authorization: Bearer <token>`.
+    pub access_token: String,
+
+    /// Always `Bearer`.
+    pub token_type: &'static str,
+
+    /// Seconds until the access token expires.
+    pub expires_in: i64,
+
+    /// Single use, every refresh returns a new one.
+    pub refresh_token: String,
+
+    /// The granted scopes, space separated.
+    pub scope: String,
+}
+
+/// Authenticate the client of a token or revocation request.
+async fn authenticate_client(
+    pool: &PgPool,
+    headers: &HeaderMap,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> Result<Client, OAuth2Error> {
+    let (client_id, client_secret) = match headers.get(header::AUTHORIZATION) {
+        Some(value) => {
+            let (id, secret) = value
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Basic "))
+                .and_then(|credentials| BASE64_STANDARD.decode(credentials).ok())
+                .and_then(|credentials| String::from_utf8(credentials).ok())
+                .and_then(|credentials| {
+                    let (id, secret) = credentials.split_once(':')?;
+                    Some((id.to_owned(), secret.to_owned()))
+                })
+                .ok_or(OAuth2Error::InvalidClient)?;
+
+            (id, Some(secret).filter(|secret| !secret.is_empty()))
+        }
+        None => (
+            client_id.ok_or(OAuth2Error::InvalidClient)?.to_owned(),
+            client_secret.map(str::to_owned),
+        ),
+    };
+
+    let client_id: i64 = client_id.parse().map_err(|_| OAuth2Error::InvalidClient)?;
+    let client = Client::find(pool, client_id)
+        .await?
+        .ok_or(OAuth2Error::InvalidClient)?;
+
+    match (&client.secret_hash, client_secret) {
+        (Some(hash), Some(secret)) if verify_secret(&secret, hash) => Ok(client),
+        (None, None) => Ok(client),
+        _ => Err(OAuth2Error::InvalidClient),
+    }
+}
+
+/// POST /api/v1/oauth2/token - exchanges a code or a refresh token for an access token.
+///
+/// Errors are answered as RFC 6749 section 5.2 prescribes.
+#[utoipa::path(
+    post,
+    path = "/api/v1/oauth2/token",
+    tag = "oauth2",
+    request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "An access token.", body = TokenResponse),
+        (status = 400, description = "Invalid request, grant or scope.", body = OAuth2ErrorResponse),
+        (status = 401, description = "Client authentication failed.", body = OAuth2ErrorResponse),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler]
+pub async fn post_token(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    request: Result<Form<TokenRequest>, FormRejection>,
+) -> Result<impl IntoResponse, OAuth2Error> {
+    let Form(request) = request.map_err(|_| OAuth2Error::InvalidRequest("Malformed form body."))?;
+
+    let client = authenticate_client(
+        &pool,
+        &headers,
+        request.client_id.as_deref(),
+        request.client_secret.as_deref(),
+    )
+    .await?;
+    let application_id = client.application_id;
+
+    let refresh_token = generate_secret();
+
+    let (user_id, scopes) = match request.grant_type.as_str() {
+        "authorization_code" => {
+            let (Some(code), Some(code_verifier)) = (&request.code, &request.code_verifier) else {
+                return Err(OAuth2Error::InvalidRequest(
+                    "`code` and `code_verifier` are required.",
+                ));
+            };
+
+            let code = oauth2::take_code(&pool, &hash_secret(code), application_id)
+                .await?
+                .filter(|code| !code.expired)
+                .ok_or(OAuth2Error::InvalidGrant("Invalid or expired code."))?;
+
+            if code.redirect_uri.is_some() && code.redirect_uri != request.redirect_uri {
+                return Err(OAuth2Error::InvalidGrant(
+                    "`redirect_uri` does not match the authorization request.",
+                ));
+            }
+
+            if !verify_pkce(code_verifier, &code.code_challenge) {
+                return Err(OAuth2Error::InvalidGrant("Invalid `code_verifier`."));
+            }
+
+            oauth2::authorize(
+                &pool,
+                code.user_id,
+                application_id,
+                &code.scope,
+                &hash_secret(&refresh_token),
+            )
+            .await?;
+
+            info!(
+                user_id = code.user_id,
+                application_id, "OAuth2 authorization granted"
+            );
+
+            let scopes = Scopes::parse(&code.scope).map_err(|_| OAuth2Error::ServerError)?;
+            (code.user_id, scopes)
+        }
+        "refresh_token" => {
+            let Some(old_refresh_token) = &request.refresh_token else {
+                return Err(OAuth2Error::InvalidRequest("`refresh_token` is required."));
+            };
+
+            let old_refresh_token_hash = hash_secret(old_refresh_token);
+            let invalid = OAuth2Error::InvalidGrant("Invalid refresh token.");
+
+            let (user_id, scope) =
+                oauth2::find_by_refresh_token(&pool, application_id, &old_refresh_token_hash)
+                    .await?
+                    .ok_or(invalid.clone())?;
+
+            let granted = Scopes::parse(&scope).map_err(|_| OAuth2Error::ServerError)?;
+            let scopes = match &request.scope {
+                Some(scope) => Scopes::parse(scope)
+                    .ok()
+                    .filter(|scopes| !scopes.is_empty() && scopes.is_subset(&granted))
+                    .ok_or(OAuth2Error::InvalidScope)?,
+                None => granted,
+            };
+
+            // Checked again, a concurrent refresh may have used the token meanwhile.
+            let rotated = oauth2::rotate_refresh_token(
+                &pool,
+                application_id,
+                &old_refresh_token_hash,
+                &hash_secret(&refresh_token),
+            )
+            .await?;
+
+            if !rotated {
+                return Err(invalid);
+            }
+
+            (user_id, scopes)
+        }
+        _ => return Err(OAuth2Error::UnsupportedGrantType),
+    };
+
+    let scope = scopes.to_string();
+    let access_token = AuthenticationToken::with_grant(
+        user_id as u64,
+        Some(Grant {
+            client_id: application_id as u64,
+            scopes,
+        }),
+    )?;
+
+    Ok((
+        no_store(),
+        Json(TokenResponse {
+            access_token: access_token.into(),
+            token_type: "Bearer",
+            expires_in: *TOKEN_EXPIRATION_TIME,
+            refresh_token,
+            scope,
+        }),
+    ))
+}
+
+/// Parameters of the revocation endpoint, form encoded. Clients authenticate like on
+/// `POST /api/v1/oauth2/token`. `token_type_hint` is accepted, but not needed.
+#[derive(Debug, serde::Deserialize, utoipa::ToSchema)]
+pub struct RevokeRequest {
+    /// An access or refresh token of the client.
+    pub token: String,
+
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// POST /api/v1/oauth2/token/revoke - revokes the authorization a token belongs to.
+///
+/// Both the access and refresh tokens stop working. Unknown tokens are no error, RFC 7009.
+#[utoipa::path(
+    post,
+    path = "/api/v1/oauth2/token/revoke",
+    tag = "oauth2",
+    request_body(content = RevokeRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The token is revoked, or was invalid."),
+        (status = 400, description = "Invalid request.", body = OAuth2ErrorResponse),
+        (status = 401, description = "Client authentication failed.", body = OAuth2ErrorResponse),
+        (status = 429, description = "Rate limited (30001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler]
+pub async fn post_revoke(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    request: Result<Form<RevokeRequest>, FormRejection>,
+) -> Result<StatusCode, OAuth2Error> {
+    let Form(request) = request.map_err(|_| OAuth2Error::InvalidRequest("Malformed form body."))?;
+
+    let client = authenticate_client(
+        &pool,
+        &headers,
+        request.client_id.as_deref(),
+        request.client_secret.as_deref(),
+    )
+    .await?;
+    let application_id = client.application_id;
+
+    let revoked =
+        oauth2::revoke_by_refresh_token(&pool, application_id, &hash_secret(&request.token))
+            .await?;
+
+    if !revoked {
+        // Maybe an access token, which names its user.
+        let access_token = AuthenticationToken::from_token(&request.token)
+            .ok()
+            .filter(|token| {
+                token
+                    .grant
+                    .as_ref()
+                    .is_some_and(|grant| grant.client_id == application_id as u64)
+            });
+
+        if let Some(access_token) = access_token {
+            oauth2::revoke(&pool, access_token.user_id as i64, application_id).await?;
+        }
+    }
+
+    info!(application_id, "OAuth2 token revoked");
+
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models;
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+    use tracing_test::traced_test;
+
+    async fn refresh(pool: &PgPool, client_id: i64, refresh_token: &str) -> (StatusCode, Value) {
+        let response = Router::new()
+            .route("/token", post(post_token))
+            .with_state(pool.clone())
+            .oneshot(
+                Request::post("/token")
+                    .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from(format!(
+                        "grant_type=refresh_token&client_id={client_id}&refresh_token={refresh_token}"
+                    )))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    #[ignore = "needs a database, run with DATABASE_URL set and --ignored"]
+    async fn test_password_reset_revokes_authorizations() {
+        dotenv::dotenv().ok();
+        std::env::set_var("HMAC_SECURITY_KEY", "TODO: secret key");
+        std::env::set_var("TOKEN_EXPIRATION_TIME", "3600");
+
+        let pool = models::connect().await.unwrap();
+        models::migrate(&pool).await.unwrap();
+
+        let name = format!("reset-{}", &generate_secret()[..8]);
+        let user = User::create(&pool, &name, None, "").await.unwrap();
+        let bot = User::create_bot(&pool, user.id, &format!("{name}-bot"))
+            .await
+            .unwrap();
+        Client::save(&pool, bot.id, &["https://app.example/cb".into()], None)
+            .await
+            .unwrap();
+
+        let refresh_token = generate_secret();
+        oauth2::authorize(
+            &pool,
+            user.id,
+            bot.id,
+            "identify",
+            &hash_secret(&refresh_token),
+        )
+        .await
+        .unwrap();
+
+        let (status, body) = refresh(&pool, bot.id, &refresh_token).await;
+        assert_eq!(status, StatusCode::OK);
+        let refresh_token = body["refresh_token"].as_str().unwrap().to_owned();
+
+        user.set_password(&pool, "").await.unwrap();
+
+        let (status, body) = refresh(&pool, bot.id, &refresh_token).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+use axum::{extract::State, Json};
+use sqlx::PgPool;
+
+use crate::{
+    models::user::User,
+    v1::{
+        error::{APIError, APIResult},
+        oauth2::{Identify, Scope},
+        session::Scoped,
+    },
+};
+
+/// A user, as seen by other users.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct UserResponse {
+    pub id: i64,
+    pub username: String,
+
+    /// Whether this is a bot, owned by another user.
+    pub bot: bool,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            bot: user.bot,
+        }
+    }
+}
+
+/// The current user, as seen by themselves.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct CurrentUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+
+    /// Left out for OAuth2 access tokens without the `email` scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+/// GET /api/v1/users/@me - the user (or bot) the token belongs to.
+#[utoipa::path(
+    get,
+    path = "/api/v1/users/@me",
+    tag = "users",
+    security(("bearer" = []), ("bot" = []), ("session" = []), ("oauth2" = ["identify"])),
+    responses(
+        (status = 200, description = "The current user.", body = CurrentUserResponse),
+        (status = 401, description = "Invalid (40003) or expired (40004) token.", body = JSONError),
+        (status = 403, description = "The access token lacks the `identify` scope (50001).", body = JSONError),
+    )
+)]
+#[axum::debug_handler(state = PgPool)]
+pub async fn get_me(
+    State(pool): State<PgPool>,
+    Scoped { auth, .. }: Scoped<Identify>,
+) -> APIResult<Json<CurrentUserResponse>> {
+    let user = User::find_by_id(&pool, auth.token.user_id as i64)
+        .await?
+        .ok_or(APIError::UnknownUser { who: None })?;
+
+    let email = user.email.clone().filter(|_| auth.has_scope(Scope::Email));
+
+    Ok(Json(CurrentUserResponse {
+        email,
+        email_verified: user.email_verified,
+        user: user.into(),
+    }))
+}
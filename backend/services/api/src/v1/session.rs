@@ -0,0 +1,545 @@
+//! Cookie based sessions for the browser client.
+//!
+//! Logging in with `"cookie": true` stores the token in the HttpOnly [SESSION_COOKIE]
+//! instead of returning it, so scripts (and XSS) can't read it. Browsers attach cookies
+//! on their own though, so every mutating request authenticated by the cookie has to
+//! repeat the readable [CSRF_COOKIE] in the [CSRF_HEADER] header (double-submit).
+//! Other sites can neither read that cookie nor set the header.
+//!
+//! The CSRF token is derived from the session token, see [AuthenticationToken::csrf_token],
+//! so a cookie planted by a sibling domain doesn't help either.
+//!
+//! An `Authorization` header always takes precedence over the cookie.
+//!
+//! OAuth2 access tokens (see [super::oauth2]) are only accepted by the [Scoped] extractor,
+//! [Authenticated] rejects them.
+
+use std::marker::PhantomData;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method},
+    response::AppendHeaders,
+};
+use sqlx::PgPool;
+
+use crate::models::{oauth2, user};
+
+use super::{
+    error::{APIError, APIResult},
+    oauth2::{RequiredScope, Scope, Scopes},
+    token::{AuthenticationToken, Grant, Scheme, TokenError, TOKEN_EXPIRATION_TIME},
+};
+
+/// HttpOnly cookie carrying the token.
+pub const SESSION_COOKIE: &str = "aurora_session";
+
+/// Cookie carrying the CSRF token, readable by the web app.
+pub const CSRF_COOKIE: &str = "aurora_csrf";
+
+/// Header the CSRF token has to be repeated in.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// How a request was authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credentials {
+    /// `Authorization: Bearer <token>`
+    Header,
+
+    /// `Authorization: Bot <token>`, see [Authenticated::deny_bots].
+    Bot,
+
+    /// The [SESSION_COOKIE].
+    Cookie,
+}
+
+/// Extractor for the token of an authenticated request, from either the `Authorization`
+/// header or the [SESSION_COOKIE]. Rejects expired and revoked tokens.
+///
+/// Bots are let through as well, use [Authenticated::deny_bots] where they don't belong.
+/// OAuth2 access tokens are rejected with [APIError::MissingAccess], see [Scoped].
+#[derive(Debug, Clone)]
+pub struct Authenticated {
+    pub token: AuthenticationToken,
+    pub credentials: Credentials,
+}
+
+impl Authenticated {
+    /// Whether the token may be used for `scope`.
+    /// Only OAuth2 access tokens are limited, every other token may do anything.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.token
+            .grant
+            .as_ref()
+            .is_none_or(|grant| grant.scopes.contains(scope))
+    }
+
+    pub fn is_bot(&self) -> bool {
+        self.credentials == Credentials::Bot
+    }
+
+    /// Reject bots with [APIError::BotsForbidden].
+    pub fn deny_bots(self) -> APIResult<Self> {
+        if self.is_bot() {
+            return Err(APIError::BotsForbidden);
+        }
+
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Authenticated
+where
+    PgPool: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = APIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let authenticated = verify(parts, &PgPool::from_ref(state)).await?;
+
+        if authenticated.token.grant.is_some() {
+            return Err(APIError::MissingAccess);
+        }
+
+        Ok(authenticated)
+    }
+}
+
+/// Like [Authenticated], but also accepting OAuth2 access tokens granted `S::SCOPE`.
+///
+/// Check further scopes with [Authenticated::has_scope].
+#[derive(Debug, Clone)]
+pub struct Scoped<S> {
+    pub auth: Authenticated,
+    scope: PhantomData<S>,
+}
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for Scoped<T>
+where
+    PgPool: FromRef<S>,
+    S: Send + Sync,
+    T: RequiredScope,
+{
+    type Rejection = APIError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let authenticated = verify(parts, &PgPool::from_ref(state)).await?;
+
+        if !authenticated.has_scope(T::SCOPE) {
+            return Err(APIError::MissingAccess);
+        }
+
+        Ok(Self {
+            auth: authenticated,
+            scope: PhantomData,
+        })
+    }
+}
+
+/// Whether the scopes of `grant` are all in the scope the user currently authorizes.
+///
+/// Consenting again may narrow the scope, tokens issued before must not outlive that.
+fn grant_authorized(grant: &Grant, authorized_scope: Option<&str>) -> bool {
+    authorized_scope
+        .and_then(|scope| Scopes::parse(scope).ok())
+        .is_some_and(|authorized| grant.scopes.is_subset(&authorized))
+}
+
+/// The outcome of [verify], kept in the request extensions so it runs once per request.
+#[derive(Clone)]
+struct Verification(APIResult<Authenticated>);
+
+/// Authenticate a request, rejecting expired and revoked tokens.
+///
+/// Failures are recorded in [crate::metrics], whether or not they are answered with.
+pub async fn verify(parts: &mut Parts, pool: &PgPool) -> APIResult<Authenticated> {
+    if let Some(Verification(result)) = parts.extensions.get::<Verification>() {
+        return result.clone();
+    }
+
+    let result = verify_token(parts, pool).await;
+
+    if let Err(err) = &result {
+        crate::metrics::record_token_failure(err);
+    }
+
+    parts.extensions.insert(Verification(result.clone()));
+    result
+}
+
+async fn verify_token(parts: &Parts, pool: &PgPool) -> APIResult<Authenticated> {
+    let (token, credentials) = authenticate(&parts.method, &parts.headers)?;
+    let bot = credentials == Credentials::Bot;
+
+    // Bot tokens don't expire, they are regenerated instead.
+    if !bot && token.expired() {
+        return Err(APIError::ExpiredToken);
+    }
+
+    let Some(status) =
+        user::session_status(pool, token.user_id as i64, token.generated_at()).await?
+    else {
+        return Err(TokenError::InvalidToken.into());
+    };
+
+    // The scheme has to match the account, or user tokens would never expire.
+    if status.bot != bot {
+        return Err(TokenError::InvalidAuthorizationHeaderFormat.into());
+    }
+
+    // Tokens issued before a password reset or regeneration are revoked.
+    if !status.valid {
+        return Err(match bot {
+            true => TokenError::InvalidToken.into(),
+            false => APIError::ExpiredToken,
+        });
+    }
+
+    // Access tokens are sent as `Bearer`, and die with their authorization.
+    if let Some(grant) = &token.grant {
+        let authorized_scope = match credentials {
+            Credentials::Header => {
+                oauth2::authorized_scope(
+                    pool,
+                    token.user_id as i64,
+                    grant.client_id as i64,
+                    token.generated_at(),
+                )
+                .await?
+            }
+            _ => None,
+        };
+
+        if !grant_authorized(grant, authorized_scope.as_deref()) {
+            return Err(TokenError::InvalidToken.into());
+        }
+    }
+
+    Ok(Authenticated { token, credentials })
+}
+
+/// Read the token of a request, checking the CSRF token if it came from a cookie.
+///
+/// Safe methods (GET, HEAD, ...) don't need a CSRF token, they must not change anything.
+pub fn authenticate(
+    method: &Method,
+    headers: &HeaderMap,
+) -> APIResult<(AuthenticationToken, Credentials)> {
+    if headers.contains_key(header::AUTHORIZATION) {
+        let (token, scheme) = AuthenticationToken::from_headers_with_scheme(headers)?;
+        let credentials = match scheme {
+            Scheme::Bearer => Credentials::Header,
+            Scheme::Bot => Credentials::Bot,
+        };
+
+        return Ok((token, credentials));
+    }
+
+    let Some(session) = cookie(headers, SESSION_COOKIE) else {
+        return Err(TokenError::MissingAuthorizationHeader.into());
+    };
+
+    let token = AuthenticationToken::from_token(session)?;
+
+    if !method.is_safe() {
+        let csrf_token = headers
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(APIError::InvalidCsrfToken)?;
+
+        token
+            .verify_csrf_token(csrf_token)
+            .map_err(|_| APIError::InvalidCsrfToken)?;
+    }
+
+    Ok((token, Credentials::Cookie))
+}
+
+/// The value of the cookie `name`, if the request has it.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// `Set-Cookie` headers starting (or renewing) a session for `token`.
+pub fn session_cookies(
+    token: &AuthenticationToken,
+) -> APIResult<AppendHeaders<[(header::HeaderName, HeaderValue); 2]>> {
+    let csrf_token = token.csrf_token()?;
+    let token: String = token.clone().into();
+    let max_age = *TOKEN_EXPIRATION_TIME;
+
+    Ok(AppendHeaders([
+        (
+            header::SET_COOKIE,
+            set_cookie(SESSION_COOKIE, &token, max_age, "/api; HttpOnly"),
+        ),
+        // The web app reads this one, from any page.
+        (
+            header::SET_COOKIE,
+            set_cookie(CSRF_COOKIE, &csrf_token, max_age, "/"),
+        ),
+    ]))
+}
+
+/// `Set-Cookie` headers ending a session.
+pub fn clear_session_cookies() -> AppendHeaders<[(header::HeaderName, HeaderValue); 2]> {
+    AppendHeaders([
+        (
+            header::SET_COOKIE,
+            set_cookie(SESSION_COOKIE, "", 0, "/api; HttpOnly"),
+        ),
+        (header::SET_COOKIE, set_cookie(CSRF_COOKIE, "", 0, "/")),
+    ])
+}
+
+fn set_cookie(name: &str, value: &str, max_age: i64, path: &str) -> HeaderValue {
+    format!("{name}={value}; Max-Age={max_age}; Path={path}; Secure; SameSite=Strict")
+        .parse()
+        .expect("Tokens are valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+    use tracing_test::traced_test;
+
+    fn setup() -> AuthenticationToken {
+        std::env::set_var("HMAC_SECURITY_KEY", "TODO: secret key");
+        std::env::set_var("TOKEN_EXPIRATION_TIME", "3600");
+
+        AuthenticationToken::new(1).unwrap()
+    }
+
+    fn cookie_headers(token: &AuthenticationToken, csrf_token: Option<&str>) -> HeaderMap {
+        let token: String = token.clone().into();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            format!("theme=dark; {SESSION_COOKIE}={token}")
+                .parse()
+                .unwrap(),
+        );
+        if let Some(csrf_token) = csrf_token {
+            headers.insert(CSRF_HEADER, csrf_token.parse().unwrap());
+        }
+
+        headers
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_cookie_parsing() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::COOKIE, "a=1; b=2=3".parse().unwrap());
+        headers.append(header::COOKIE, "c=".parse().unwrap());
+
+        assert_eq!(cookie(&headers, "a"), Some("1"));
+        assert_eq!(cookie(&headers, "b"), Some("2=3"));
+        assert_eq!(cookie(&headers, "c"), Some(""));
+        assert_eq!(cookie(&headers, "d"), None);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_authenticate_with_header() {
+        let token = setup();
+        let token_string: String = token.clone().into();
+
+        // The header wins, and needs no CSRF token.
+        let mut headers = cookie_headers(&AuthenticationToken::new(2).unwrap(), None);
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {token_string}").parse().unwrap(),
+        );
+
+        let (token, credentials) = authenticate(&Method::POST, &headers).unwrap();
+        assert_eq!(token.user_id, 1);
+        assert_eq!(credentials, Credentials::Header);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_authenticate_bot() {
+        let token = setup();
+        let token_string: String = token.clone().into();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bot {token_string}").parse().unwrap(),
+        );
+
+        let (token, credentials) = authenticate(&Method::POST, &headers).unwrap();
+        assert_eq!(credentials, Credentials::Bot);
+
+        let authenticated = Authenticated { token, credentials };
+        assert!(authenticated.is_bot());
+        assert!(matches!(
+            authenticated.deny_bots(),
+            Err(APIError::BotsForbidden)
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_scopes_of_access_tokens() {
+        let token = setup();
+
+        let user = Authenticated {
+            token,
+            credentials: Credentials::Header,
+        };
+        assert!(user.has_scope(Scope::Email));
+
+        let grant = Grant {
+            client_id: 7,
+            scopes: Scopes::parse("identify").unwrap(),
+        };
+        let application = Authenticated {
+            token: AuthenticationToken::with_grant(1, Some(grant)).unwrap(),
+            credentials: Credentials::Header,
+        };
+        assert!(application.has_scope(Scope::Identify));
+        assert!(!application.has_scope(Scope::Email));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_narrowed_authorization() {
+        let grant = Grant {
+            client_id: 7,
+            scopes: Scopes::parse("identify email").unwrap(),
+        };
+        assert!(grant_authorized(&grant, Some("email identify")));
+
+        // The user consented again, to less.
+        assert!(!grant_authorized(&grant, Some("identify")));
+        assert!(!grant_authorized(&grant, None));
+
+        let grant = Grant {
+            client_id: 7,
+            scopes: Scopes::parse("identify").unwrap(),
+        };
+        assert!(grant_authorized(&grant, Some("identify email")));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_authenticate_with_cookie() {
+        let token = setup();
+
+        // Safe methods need no CSRF token.
+        let (authenticated, credentials) =
+            authenticate(&Method::GET, &cookie_headers(&token, None)).unwrap();
+        assert_eq!(authenticated.user_id, 1);
+        assert_eq!(credentials, Credentials::Cookie);
+
+        let csrf_token = token.csrf_token().unwrap();
+        let headers = cookie_headers(&token, Some(&csrf_token));
+        assert!(authenticate(&Method::POST, &headers).is_ok());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_authenticate_cookie_requires_csrf_token() {
+        let token = setup();
+
+        assert!(matches!(
+            authenticate(&Method::POST, &cookie_headers(&token, None)),
+            Err(APIError::InvalidCsrfToken)
+        ));
+
+        // The CSRF token of another session.
+        let other = AuthenticationToken::new(2).unwrap().csrf_token().unwrap();
+        assert!(matches!(
+            authenticate(&Method::DELETE, &cookie_headers(&token, Some(&other))),
+            Err(APIError::InvalidCsrfToken)
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_authenticate_without_credentials() {
+        setup();
+
+        assert!(matches!(
+            authenticate(&Method::GET, &HeaderMap::new()),
+            Err(APIError::InvalidToken(
+                TokenError::MissingAuthorizationHeader
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_session_cookies() {
+        let token = setup();
+
+        let AppendHeaders([(_, session), (_, csrf)]) = session_cookies(&token).unwrap();
+        let session = session.to_str().unwrap();
+        let csrf = csrf.to_str().unwrap();
+
+        assert!(session.starts_with(&format!("{SESSION_COOKIE}=")));
+        assert!(session.contains("HttpOnly"));
+        assert!(session.contains("Secure"));
+        assert!(session.contains("SameSite=Strict"));
+
+        assert!(csrf.starts_with(&format!("{CSRF_COOKIE}={}", token.csrf_token().unwrap())));
+        assert!(!csrf.contains("HttpOnly"));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_verify_records_failures_once() {
+        setup();
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://aurora@127.0.0.1:1/aurora")
+            .unwrap();
+
+        let failures = || {
+            prometheus::gather()
+                .iter()
+                .filter(|family| family.get_name() == "aurora_token_verification_failures_total")
+                .flat_map(|family| family.get_metric())
+                .filter(|metric| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_value() == "UserIdParsing")
+                })
+                .map(|metric| metric.get_counter().get_value())
+                .sum::<f64>()
+        };
+        let before = failures();
+
+        // The user ID decodes to `a`.
+        let (mut parts, _) = axum::http::Request::get("/")
+            .header(header::AUTHORIZATION, "Bearer YQ==.x.y")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        // Rate limiting verifies the token before the extractors do.
+        for _ in 0..2 {
+            assert!(matches!(
+                verify(&mut parts, &pool).await,
+                Err(APIError::InvalidToken(TokenError::UserIdParsing))
+            ));
+        }
+
+        assert_eq!(failures() - before, 1.0);
+    }
+}
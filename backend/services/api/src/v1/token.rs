@@ -4,6 +4,8 @@ use hmac::{Hmac, Mac};
 use sha2::Sha512;
 use time::{Date, Time, UtcOffset};
 
+use super::oauth2::Scopes;
+
 #[derive(thiserror::Error, Debug, Clone, PartialEq)]
 pub enum TokenError {
     #[error("Failed to generate HMAC for token.")]
@@ -33,6 +35,9 @@ pub enum TokenError {
     #[error("Invalid token.")]
     InvalidToken,
 
+    #[error("Token expired.")]
+    Expired,
+
     #[error("Missing authorization header.")]
     MissingAuthorizationHeader,
 
@@ -60,6 +65,15 @@ type Result<T> = std::result::Result<T, TokenError>;
 /// <user_id>.<generation_time>.<hmac>
 /// ```
 ///
+/// OAuth2 access tokens also carry their [Grant], covered by the HMAC as well:
+/// ```text
+/// // <client_id>       := Base64(<string>)
+/// // <scopes>          := Base64(<string>), e.g. `identify email`
+/// // <hmac>            := Base64(HMAC<SHA512>(<user_id>.<generation_time>.<client_id>.<scopes>))
+///
+/// <user_id>.<generation_time>.<client_id>.<scopes>.<hmac>
+/// ```
+///
 #[derive(Debug, Clone)]
 pub struct AuthenticationToken {
     /// The user ID of the user this token belongs to.
@@ -70,6 +84,17 @@ pub struct AuthenticationToken {
 
     /// The HMAC of the token. It is composed from the generation time and the user ID. + a secret key. [struct@HMAC_SECURITY_KEY]
     pub hmac: Vec<u8>,
+
+    /// What the user granted an application, for OAuth2 access tokens. See [super::oauth2].
+    pub grant: Option<Grant>,
+}
+
+/// The part of an OAuth2 access token telling what it may be used for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    /// The ID of the application the token was issued to.
+    pub client_id: u64,
+    pub scopes: Scopes,
 }
 
 lazy_static! {
@@ -79,7 +104,7 @@ lazy_static! {
         .into_bytes();
 
     /// Amount of time in seconds before a token expires.
-    static ref TOKEN_EXPIRATION_TIME: i64 = std::env::var("TOKEN_EXPIRATION_TIME")
+    pub static ref TOKEN_EXPIRATION_TIME: i64 = std::env::var("TOKEN_EXPIRATION_TIME")
         .expect("TOKEN_EXPIRATION_TIME must be set")
         .parse()
         .expect("TOKEN_EXPIRATION_TIME must be a valid integer");
@@ -95,10 +120,16 @@ pub const FIRST_EPOCH: time::OffsetDateTime =
 
 impl AuthenticationToken {
     pub fn new(user_id: u64) -> Result<Self> {
+        Self::with_grant(user_id, None)
+    }
+
+    /// Create an OAuth2 access token if `grant` is set, see [super::oauth2].
+    pub fn with_grant(user_id: u64, grant: Option<Grant>) -> Result<Self> {
         let mut token = AuthenticationToken {
             user_id,
             generation_time: 0,
             hmac: Vec::new(),
+            grant,
         };
         token.update_secure_parts()?;
         Ok(token)
@@ -120,14 +151,7 @@ impl AuthenticationToken {
 
         self.generation_time = current_based_on_epoch.whole_milliseconds() as i64; // This will overflow in 292 million years. I think we are good.
 
-        hmac.update(
-            format!(
-                "{user_id}.{generation_time}",
-                user_id = self.user_id,
-                generation_time = self.generation_time
-            )
-            .as_bytes(),
-        );
+        hmac.update(self.signed_message().as_bytes());
 
         self.hmac = hmac.finalize().into_bytes().to_vec();
 
@@ -143,19 +167,74 @@ impl AuthenticationToken {
         let mut hmac = Hmac::<Sha512>::new_from_slice(&HMAC_SECURITY_KEY)
             .map_err(|_| TokenError::HmacGeneration)?;
 
+        hmac.update(self.signed_message().as_bytes());
+
+        hmac.verify_slice(&self.hmac)
+            .map_err(|_| TokenError::HmacVerification)?;
+
+        Ok(())
+    }
+
+    /// What the HMAC is computed over.
+    fn signed_message(&self) -> String {
+        match &self.grant {
+            None => format!(
+                "{user_id}.{generation_time}",
+                user_id = self.user_id,
+                generation_time = self.generation_time
+            ),
+            Some(grant) => format!(
+                "{user_id}.{generation_time}.{client_id}.{scopes}",
+                user_id = self.user_id,
+                generation_time = self.generation_time,
+                client_id = grant.client_id,
+                scopes = grant.scopes
+            ),
+        }
+    }
+
+    /// CSRF token bound to this token, for cookie sessions. See [super::session].
+    ///
+    /// It is derived from the token, so it needs no storage and is useless with any other session.
+    pub fn csrf_token(&self) -> Result<String> {
+        Ok(BASE64.encode(self.csrf_hmac()?.finalize().into_bytes()))
+    }
+
+    /// Verify a CSRF token sent along with this token.
+    ///
+    /// # Errors
+    /// - [TokenError::HmacGeneration] Failed to create HMAC for validation.
+    /// - [TokenError::HmacDecoding] The CSRF token is not valid Base64.
+    /// - [TokenError::HmacVerification] The CSRF token does not belong to this token.
+    pub fn verify_csrf_token(&self, csrf_token: &str) -> Result<()> {
+        let csrf_token = BASE64
+            .decode(csrf_token)
+            .map_err(|_| TokenError::HmacDecoding)?;
+
+        self.csrf_hmac()?
+            .verify_slice(&csrf_token)
+            .map_err(|_| TokenError::HmacVerification)
+    }
+
+    fn csrf_hmac(&self) -> Result<Hmac<Sha512>> {
+        let mut hmac = keyed_hmac()?;
+
+        // Prefixed, so a CSRF token can never pass as the HMAC of a token.
         hmac.update(
             format!(
-                "{user_id}.{generation_time}",
+                "csrf.{user_id}.{generation_time}",
                 user_id = self.user_id,
                 generation_time = self.generation_time
             )
             .as_bytes(),
         );
 
-        hmac.verify_slice(&self.hmac)
-            .map_err(|_| TokenError::HmacVerification)?;
+        Ok(hmac)
+    }
 
-        Ok(())
+    /// When the token was generated.
+    pub fn generated_at(&self) -> time::OffsetDateTime {
+        FIRST_EPOCH + time::Duration::milliseconds(self.generation_time)
     }
 
     /// Checks if the token is expired.
@@ -163,28 +242,43 @@ impl AuthenticationToken {
         let current_based_on_epoch = time::OffsetDateTime::now_utc() - FIRST_EPOCH;
         let current_time = current_based_on_epoch.whole_milliseconds() as i64;
 
-        current_time - self.generation_time > (*TOKEN_EXPIRATION_TIME * 1000)
+        current_time.saturating_sub(self.generation_time) > (*TOKEN_EXPIRATION_TIME * 1000)
     }
 
     /// Create a token from a string.
     ///
     /// # Errors
     ///
+    /// - everything that [AuthenticationToken::decode] can return.
+    /// - everything that [AuthenticationToken::verify] can return.
+    pub fn from_token<S>(token: &S) -> Result<Self>
+    where
+        S: AsRef<str> + ?Sized,
+    {
+        let token = Self::decode(token)?;
+        token.verify()?;
+
+        Ok(token)
+    }
+
+    /// Decode a token from a string, without verifying it. Only use it for inspecting tokens.
+    ///
+    /// # Errors
+    ///
     /// - [TokenError::InvalidFormat] The token is not in the correct format.
     /// - [TokenError::UserIdBase64Decoding] Failed to decode the user ID from Base64.
     /// - [TokenError::UserIdUtf8Decoding] Failed to decode the user ID from UTF8 (via Base64).
     /// - [TokenError::UserIdParsing] Failed to parse the user ID from string.
     /// - [TokenError::GenerationTimeDecoding] Failed to decode the generation time from Base64.
     /// - [TokenError::HmacDecoding] Failed to decode the HMAC from Base64.
-    /// - everything that [AuthenticationToken::verify] can return.
-    pub fn from_token<S>(token: &S) -> Result<Self>
+    pub fn decode<S>(token: &S) -> Result<Self>
     where
         S: AsRef<str> + ?Sized,
     {
         let token = token.as_ref();
 
         let components = token.split('.').collect::<Vec<&str>>();
-        if components.len() < 3 {
+        if components.len() != 3 && components.len() != 5 {
             return Err(TokenError::InvalidFormat);
         }
 
@@ -196,11 +290,9 @@ impl AuthenticationToken {
             let utf8_decoded =
                 String::from_utf8(base64_decoded).map_err(|_| TokenError::UserIdUtf8Decoding)?;
 
-            let u64_decoded = utf8_decoded
+            utf8_decoded
                 .parse()
-                .map_err(|_| TokenError::UserIdParsing)?;
-
-            u64_decoded
+                .map_err(|_| TokenError::UserIdParsing)?
         };
 
         //
@@ -211,35 +303,43 @@ impl AuthenticationToken {
                 .decode(components[1]) //
                 .map_err(|_| TokenError::GenerationTimeDecoding)?;
 
-            let mut bytes = [0u8; 8];
-            bytes.copy_from_slice(&base64_decoded);
+            let bytes = base64_decoded
+                .try_into()
+                .map_err(|_| TokenError::GenerationTimeDecoding)?;
 
             i64::from_be_bytes(bytes)
         };
 
         //
-        // Decode HMAC.
+        // Decode the grant of access tokens.
         //
-        let hmac: Vec<u8> = {
-            let base64_decoded = BASE64
-                .decode(components[2]) //
-                .map_err(|_| TokenError::HmacDecoding)?;
-
-            base64_decoded
+        let grant = match components.len() {
+            5 => Some(Grant {
+                client_id: decode_string(components[2])?
+                    .parse()
+                    .map_err(|_| TokenError::InvalidFormat)?,
+                scopes: Scopes::parse(&decode_string(components[3])?)
+                    .map_err(|_| TokenError::InvalidFormat)?,
+            }),
+            _ => None,
         };
 
-        let token = Self {
+        //
+        // Decode HMAC.
+        //
+        let hmac: Vec<u8> = BASE64
+            .decode(components[components.len() - 1]) //
+            .map_err(|_| TokenError::HmacDecoding)?;
+
+        Ok(Self {
             user_id,
-            generation_time: generation_time as i64,
+            generation_time,
             hmac,
-        };
-
-        token.verify()?;
-
-        Ok(token)
+            grant,
+        })
     }
 
-    /// Shortcut to create a token from headers.
+    /// Shortcut to create a token from headers, either `Bearer <token>` or `Bot <token>`.
     ///
     /// # Errors
     /// - [TokenError::MissingAuthorizationHeader] Authorization header is missing.
@@ -247,26 +347,66 @@ impl AuthenticationToken {
     /// - [TokenError::InvalidAuthorizationHeaderFormat] Authorization header is invalid.
     /// - everything that [AuthenticationToken::from_token] can return.
     pub fn from_headers(headers: &HeaderMap) -> Result<Self> {
+        Self::from_headers_with_scheme(headers).map(|(token, _)| token)
+    }
+
+    /// Like [AuthenticationToken::from_headers], also telling which scheme was used.
+    pub fn from_headers_with_scheme(headers: &HeaderMap) -> Result<(Self, Scheme)> {
         let auth_header = headers
             .get("Authorization")
             .ok_or(TokenError::MissingAuthorizationHeader)?
             .to_str()
             .map_err(|_| TokenError::InvalidAuthorizationHeader)?;
 
-        if !auth_header.starts_with("Bearer ") {
+        let (scheme, token) = if let Some(token) = auth_header.strip_prefix("Bearer ") {
+            (Scheme::Bearer, token)
+        } else if let Some(token) = auth_header.strip_prefix("Bot ") {
+            (Scheme::Bot, token)
+        } else {
             return Err(TokenError::InvalidAuthorizationHeaderFormat);
-        }
-
-        let token = auth_header.trim_start_matches("Bearer ");
+        };
 
-        Self::from_token(token)
+        Ok((Self::from_token(token)?, scheme))
     }
 }
 
+/// The `Authorization` schemes tokens are accepted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Tokens of users, they expire.
+    Bearer,
+
+    /// Tokens of bots, they are valid until regenerated.
+    Bot,
+}
+
+/// HMAC keyed with [struct@HMAC_SECURITY_KEY], for everything we sign.
+pub(super) fn keyed_hmac() -> Result<Hmac<Sha512>> {
+    Hmac::<Sha512>::new_from_slice(&HMAC_SECURITY_KEY).map_err(|_| TokenError::HmacGeneration)
+}
+
+/// Decode a Base64 encoded UTF8 component of a token.
+fn decode_string(component: &str) -> Result<String> {
+    let bytes = BASE64
+        .decode(component)
+        .map_err(|_| TokenError::InvalidFormat)?;
+
+    String::from_utf8(bytes).map_err(|_| TokenError::InvalidFormat)
+}
+
 impl From<AuthenticationToken> for String {
     fn from(token: AuthenticationToken) -> Self {
+        let grant = match &token.grant {
+            Some(grant) => format!(
+                "{client_id}.{scopes}.",
+                client_id = BASE64.encode(grant.client_id.to_string()),
+                scopes = BASE64.encode(grant.scopes.to_string()),
+            ),
+            None => String::new(),
+        };
+
         format!(
-            "{user_id}.{generation_time}.{hmac}",
+            "{user_id}.{generation_time}.{grant}{hmac}",
             user_id = BASE64.encode(token.user_id.to_string()),
             generation_time = BASE64.encode(token.generation_time.to_be_bytes()),
             hmac = BASE64.encode(token.hmac),
@@ -275,6 +415,7 @@ impl From<AuthenticationToken> for String {
 }
 
 #[cfg(test)]
+#[allow(clippy::useless_format)]
 mod tests {
     use super::*;
     use axum::http::HeaderValue;
@@ -398,6 +539,50 @@ mod tests {
 
         // Valid token but with invalid HMAC Base64.
         assert!(AuthenticationToken::from_token("MTgzNzE4MjYwNjc0NTI3MjMy.AAAAAAAA0Fw=.ijhqOyJ7NX+oia4iDUt+T9uC5RpJcIRq/5Xx7ClQQ1HiP2yRSzkw0nckaacw3dzmmj5OGx8zEQu7GF6h/l5Fjw!=").is_err_and(|e| e == TokenError::HmacDecoding));
+
+        // Generation time of the wrong size.
+        assert!(AuthenticationToken::from_token("MTgzNzE4MjYwNjc0NTI3MjMy.AAAA.ijhqOyJ7NX+oia4iDUt+T9uC5RpJcIRq/5Xx7ClQQ1HiP2yRSzkw0nckaacw3dzmmj5OGx8zEQu7GF6h/l5Fjw==").is_err_and(|e| e == TokenError::GenerationTimeDecoding));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_token_decode_without_verifying() {
+        setup();
+
+        let token = AuthenticationToken::decode(INVALID_HMAC_TOKEN).unwrap();
+        assert_eq!(token.user_id, 183718260674527232);
+        assert_eq!(token.verify(), Err(TokenError::HmacVerification));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_token_with_grant() {
+        setup();
+
+        let grant = Grant {
+            client_id: 7,
+            scopes: Scopes::parse("identify email").unwrap(),
+        };
+        let token_string: String = AuthenticationToken::with_grant(1, Some(grant.clone()))
+            .unwrap()
+            .into();
+        assert_eq!(token_string.split('.').count(), 5);
+
+        let token = AuthenticationToken::from_token(&token_string).unwrap();
+        assert_eq!(token.user_id, 1);
+        assert_eq!(token.grant, Some(grant));
+
+        // Changing the scopes breaks the HMAC.
+        let mut components: Vec<&str> = token_string.split('.').collect();
+        let narrowed = BASE64.encode("identify");
+        components[3] = &narrowed;
+        assert!(AuthenticationToken::from_token(&components.join("."))
+            .is_err_and(|e| e == TokenError::HmacVerification));
+
+        // Neither can the grant be stripped.
+        let stripped = format!("{}.{}.{}", components[0], components[1], components[4]);
+        assert!(AuthenticationToken::from_token(&stripped)
+            .is_err_and(|e| e == TokenError::HmacVerification));
     }
 
     #[tokio::test]
@@ -426,6 +611,24 @@ mod tests {
         assert!(token.verify().is_ok());
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_token_from_bot_header() {
+        setup();
+
+        let token_string: String = AuthenticationToken::new(1).unwrap().into();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bot {}", token_string).parse().unwrap(),
+        );
+
+        let (token, scheme) = AuthenticationToken::from_headers_with_scheme(&headers).unwrap();
+        assert_eq!(token.user_id, 1);
+        assert_eq!(scheme, Scheme::Bot);
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_token_from_invalid_header() {
@@ -470,6 +673,28 @@ mod tests {
             .is_err_and(|e| e == TokenError::MissingAuthorizationHeader));
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_csrf_token() {
+        setup();
+
+        let token = AuthenticationToken::new(1).unwrap();
+        let csrf_token = token.csrf_token().unwrap();
+        assert!(token.verify_csrf_token(&csrf_token).is_ok());
+
+        // Bound to the token it was created for.
+        let mut other = token.clone();
+        other.user_id = 2;
+        assert!(other
+            .verify_csrf_token(&csrf_token)
+            .is_err_and(|e| e == TokenError::HmacVerification));
+
+        // Not interchangeable with the HMAC of the token.
+        assert!(token
+            .verify_csrf_token(&BASE64.encode(&token.hmac))
+            .is_err_and(|e| e == TokenError::HmacVerification));
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_token_expired() {
@@ -480,5 +705,9 @@ mod tests {
 
         token.generation_time = 0;
         assert!(token.expired());
+        assert_eq!(token.generated_at(), FIRST_EPOCH);
+
+        token.generation_time = i64::MIN;
+        assert!(token.expired());
     }
 }
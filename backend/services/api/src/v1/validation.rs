@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Query, Request},
+    http::{header, request::Parts},
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::error::Category;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use super::error::APIError;
+
+/// Key for errors which are not about a single field, e.g. malformed JSON.
+pub const BODY_ERRORS: &str = "_errors";
+
+/// A single problem with a field of a request body.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, utoipa::ToSchema)]
+pub struct FieldError {
+    /// Machine readable reason, e.g. `length` or `required`.
+    pub code: String,
+
+    /// Human readable description of the problem.
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Problems with a request body, by the field they belong to.
+pub type FieldErrors = BTreeMap<String, Vec<FieldError>>;
+
+impl From<ValidationErrors> for APIError {
+    fn from(errors: ValidationErrors) -> Self {
+        APIError::InvalidFormBody {
+            errors: field_errors(&errors),
+        }
+    }
+}
+
+/// The [FieldErrors] of failed validations, with human readable messages.
+///
+/// Errors of nested structs and lists are keyed by their path, e.g. `settings.theme` or
+/// `redirect_uris[0]`.
+pub fn field_errors(errors: &ValidationErrors) -> FieldErrors {
+    let mut field_errors = FieldErrors::new();
+    collect_field_errors(errors, None, &mut field_errors);
+    field_errors
+}
+
+fn collect_field_errors(errors: &ValidationErrors, parent: Option<&str>, out: &mut FieldErrors) {
+    for (field, kind) in errors.errors() {
+        let path = match parent {
+            Some(parent) => format!("{parent}.{field}"),
+            None => field.to_string(),
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                out.entry(path).or_default().extend(
+                    errors
+                        .iter()
+                        .map(|err| FieldError::new(err.code.clone(), message(err))),
+                );
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, Some(&path), out),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(errors, Some(&format!("{path}[{index}]")), out);
+                }
+            }
+        }
+    }
+}
+
+/// Human readable message of a validation error.
+pub fn message(err: &ValidationError) -> String {
+    match &err.message {
+        Some(message) => message.to_string(),
+        None => describe(err),
+    }
+}
+
+/// Fallback message for validation errors which don't bring their own.
+fn describe(err: &ValidationError) -> String {
+    let param = |name: &str| err.params.get(name).map(|value| value.to_string());
+
+    match err.code.as_ref() {
+        "length" => match (param("min"), param("max"), param("equal")) {
+            (_, _, Some(equal)) => format!("Must be exactly {equal} characters long."),
+            (Some(min), Some(max), _) => {
+                format!("Must be between {min} and {max} characters long.")
+            }
+            (Some(min), None, _) => format!("Must be at least {min} characters long."),
+            (None, Some(max), _) => format!("Must be at most {max} characters long."),
+            (None, None, _) => "Invalid length.".into(),
+        },
+        "range" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("Must be between {min} and {max}."),
+            (Some(min), None) => format!("Must be at least {min}."),
+            (None, Some(max)) => format!("Must be at most {max}."),
+            (None, None) => "Out of range.".into(),
+        },
+        "email" => "Not a valid email address.".into(),
+        "url" => "Not a valid URL.".into(),
+        _ => "Invalid value.".into(),
+    }
+}
+
+/// Deserialize a string without surrounding whitespace, so it is validated like it is used.
+///
+/// Use as `#[serde(deserialize_with = "trimmed")]`.
+pub fn trimmed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let value = String::deserialize(deserializer)?;
+
+    Ok(match value.trim() {
+        trimmed if trimmed.len() == value.len() => value,
+        trimmed => trimmed.to_owned(),
+    })
+}
+
+/// Rules for new passwords, wherever they are set.
+///
+/// Use as `#[validate(custom(function = "validate_password"))]`.
+pub fn validate_password(password: &str) -> Result<(), ValidationError> {
+    const MIN: u64 = 8;
+    const MAX: u64 = 1024;
+
+    if (MIN..=MAX).contains(&(password.chars().count() as u64)) {
+        return Ok(());
+    }
+
+    let mut err = ValidationError::new("length");
+    err.add_param("min".into(), &MIN);
+    err.add_param("max".into(), &MAX);
+    Err(err)
+}
+
+/// Build the error for a body that could not be deserialized, at `path` within it.
+fn deserialize_error(path: String, err: serde_json::Error) -> APIError {
+    let (field, error) = match err.classify() {
+        // The body itself is broken, no field is to blame.
+        Category::Syntax | Category::Eof | Category::Io => (
+            BODY_ERRORS.to_owned(),
+            FieldError::new("invalid_json", strip_position(&err.to_string())),
+        ),
+        Category::Data => {
+            let message = strip_position(&err.to_string());
+
+            // serde reports missing fields on the parent, move them to the field itself.
+            match message
+                .strip_prefix("missing field `")
+                .and_then(|rest| rest.strip_suffix('`'))
+            {
+                Some(missing) => (
+                    match path.as_str() {
+                        "." => missing.to_owned(),
+                        parent => format!("{parent}.{missing}"),
+                    },
+                    FieldError::new("required", "This field is required."),
+                ),
+                None => (path, FieldError::new("invalid_type", message)),
+            }
+        }
+    };
+
+    APIError::InvalidFormBody {
+        errors: FieldErrors::from([(field, vec![error])]),
+    }
+}
+
+/// serde_json appends ` at line 1 column 2` to its messages, which means nothing to users.
+fn strip_position(message: &str) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_owned(),
+        None => message.to_owned(),
+    }
+}
+
+/// JSON body extractor which validates the body.
+///
+/// Unlike [axum::Json], every failure is answered with [APIError::InvalidFormBody],
+/// pointing at the fields at fault.
+#[derive(Debug, Clone)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = APIError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(|content_type| content_type.split(';').next().unwrap_or_default().trim())
+            .is_some_and(|essence| {
+                essence == "application/json"
+                    || (essence.starts_with("application/") && essence.ends_with("+json"))
+            });
+
+        if !is_json {
+            return Err(APIError::InvalidFormBody {
+                errors: FieldErrors::from([(
+                    BODY_ERRORS.to_owned(),
+                    vec![FieldError::new(
+                        "content_type",
+                        "Expected request with `Content-Type: application/json`.",
+                    )],
+                )]),
+            });
+        }
+
+        let bytes =
+            Bytes::from_request(request, state)
+                .await
+                .map_err(|err| APIError::InvalidFormBody {
+                    errors: FieldErrors::from([(
+                        BODY_ERRORS.to_owned(),
+                        vec![FieldError::new("body", err.body_text())],
+                    )]),
+                })?;
+
+        let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+        let value: T = serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(|err| deserialize_error(err.path().to_string(), err.into_inner()))?;
+
+        // Only whitespace may follow the value.
+        deserializer
+            .end()
+            .map_err(|err| deserialize_error(".".to_owned(), err))?;
+
+        value.validate()?;
+
+        Ok(Self(value))
+    }
+}
+
+/// Query string extractor which validates the parameters, like [ValidatedJson].
+#[derive(Debug, Clone)]
+pub struct ValidatedQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = APIError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) =
+            Query::<T>::try_from_uri(&parts.uri).map_err(|err| APIError::InvalidFormBody {
+                errors: FieldErrors::from([(
+                    BODY_ERRORS.to_owned(),
+                    vec![FieldError::new("invalid_query", err.body_text())],
+                )]),
+            })?;
+
+        value.validate()?;
+
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, routing::post, Router};
+    use tower::ServiceExt;
+    use tracing_test::traced_test;
+
+    #[derive(Debug, serde::Deserialize, Validate)]
+    struct Signup {
+        #[serde(deserialize_with = "trimmed")]
+        #[validate(length(min = 2, max = 32))]
+        username: String,
+
+        #[validate(email)]
+        email: String,
+
+        age: u8,
+
+        #[serde(default)]
+        #[validate(nested)]
+        links: Vec<Link>,
+    }
+
+    #[derive(Debug, serde::Deserialize, Validate)]
+    struct Link {
+        #[validate(url)]
+        url: String,
+    }
+
+    async fn post_json(content_type: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let app = Router::new().route(
+            "/",
+            post(|ValidatedJson(signup): ValidatedJson<Signup>| async move {
+                format!("{} ({})", signup.username, signup.age)
+            }),
+        );
+
+        let response = app
+            .oneshot(
+                Request::post("/")
+                    .header(header::CONTENT_TYPE, content_type)
+                    .body(Body::from(body.to_owned()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_valid_body() {
+        let (status, _) = post_json(
+            "application/json",
+            r#"{"username": "aurora", "email": "aurora@example.com", "age": 3}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_validation_errors() {
+        let (status, body) = post_json(
+            "application/json",
+            r#"{"username": "a", "email": "nope", "age": 3}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], 50035);
+        assert_eq!(body["errors"]["username"][0]["code"], "length");
+        assert_eq!(
+            body["errors"]["username"][0]["message"],
+            "Must be between 2 and 32 characters long."
+        );
+        assert_eq!(body["errors"]["email"][0]["code"], "email");
+    }
+
+    #[test]
+    fn test_validate_password() {
+        assert!(validate_password("correct horse").is_ok());
+        assert!(validate_password(&"ü".repeat(1024)).is_ok());
+
+        let err = validate_password("short").unwrap_err();
+        assert_eq!(err.code, "length");
+        assert_eq!(message(&err), "Must be between 8 and 1024 characters long.");
+        assert!(validate_password(&"a".repeat(1025)).is_err());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_trimmed_before_validation() {
+        let (status, body) = post_json(
+            "application/json",
+            r#"{"username": "  a  ", "email": "aurora@example.com", "age": 3}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"]["username"][0]["code"], "length");
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_missing_field() {
+        let (status, body) =
+            post_json("application/json", r#"{"username": "aurora", "age": 3}"#).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"]["email"][0]["code"], "required");
+        assert_eq!(
+            body["errors"]["email"][0]["message"],
+            "This field is required."
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_nested_errors() {
+        let (status, body) = post_json(
+            "application/json",
+            r#"{"username": "aurora", "email": "aurora@example.com", "age": 3, "links": [{}]}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"]["links[0].url"][0]["code"], "required");
+
+        let (status, body) = post_json(
+            "application/json",
+            r#"{"username": "aurora", "email": "aurora@example.com", "age": 3,
+                "links": [{"url": "https://aurora.example"}, {"url": "nope"}]}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"]["links[1].url"][0]["code"], "url");
+        assert_eq!(
+            body["errors"]["links[1].url"][0]["message"],
+            "Not a valid URL."
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_invalid_type() {
+        let (status, body) = post_json(
+            "application/json",
+            r#"{"username": "aurora", "email": "aurora@example.com", "age": 300}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"]["age"][0]["code"], "invalid_type");
+        assert_eq!(
+            body["errors"]["age"][0]["message"],
+            "invalid value: integer `300`, expected u8"
+        );
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_validated_query() {
+        #[derive(Debug, serde::Deserialize, Validate)]
+        struct Search {
+            #[validate(length(min = 2))]
+            query: String,
+        }
+
+        let app = Router::new().route(
+            "/",
+            axum::routing::get(
+                |ValidatedQuery(search): ValidatedQuery<Search>| async move { search.query },
+            ),
+        );
+
+        for (uri, expected) in [
+            ("/?query=aurora", StatusCode::OK),
+            ("/?query=a", StatusCode::BAD_REQUEST),
+            ("/", StatusCode::BAD_REQUEST),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), expected, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_malformed_body() {
+        let (status, body) = post_json("application/json", r#"{"username": "#).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"][BODY_ERRORS][0]["code"], "invalid_json");
+
+        let (status, body) = post_json(
+            "application/json",
+            r#"{"username": "aurora", "email": "aurora@example.com", "age": 3} {}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"][BODY_ERRORS][0]["code"], "invalid_json");
+
+        let (status, body) = post_json("text/plain", r#"{}"#).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"][BODY_ERRORS][0]["code"], "content_type");
+    }
+}